@@ -0,0 +1,81 @@
+//! Parsing throughput of the responses the bot gets on every poll.
+//! Run with `cargo bench -p csgo_server`
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use csgo_server::info::{ServerEnvironment, ServerInfo, ServerType};
+use csgo_server::players::{Player, Players};
+use csgo_server::rules::Rules;
+
+fn server_info() -> ServerInfo {
+    ServerInfo {
+        protocol: 17,
+        name: "meowdz | danger zone | sirocco".into(),
+        raw_name: Box::from(&b"meowdz | danger zone | sirocco"[..]),
+        map: "dz_sirocco".into(),
+        folder: "csgo".into(),
+        game: "Counter-Strike: Global Offensive".into(),
+        id: 730,
+        players: 16,
+        max_players: 18,
+        bots: 0,
+        server_type: ServerType::Dedicated,
+        server_environment: ServerEnvironment::Linux,
+        public: true,
+        vac: true,
+        the_ship: None,
+        version: "1.38.8.1".into(),
+        edf: 0xB1,
+        port: Some(27015),
+        steam_id: Some(90_000_000_000_000_000),
+        source_tv: None,
+        keywords: Some("dangerzone,empty,secure".into()),
+        game_id: Some(730),
+        goldsrc: None,
+    }
+}
+
+fn players() -> Players {
+    Players(
+        (0..18)
+            .map(|i| {
+                let name = format!("a rather long player name #{i}");
+
+                Player {
+                    index: i,
+                    raw_name: Box::from(name.as_bytes()),
+                    name: name.into(),
+                    score: i as i32,
+                    duration: 600.0 * i as f32,
+                    the_ship: None,
+                }
+            })
+            .collect(),
+    )
+}
+
+fn rules() -> Rules {
+    Rules(
+        (0..200)
+            .map(|i| (format!("sv_dz_rule_{i}").into(), i.to_string().into()))
+            .collect(),
+    )
+}
+
+fn parse(c: &mut Criterion) {
+    let info = server_info().encode();
+    let players = players().encode();
+    let rules = rules().encode();
+
+    c.bench_function("info", |b| {
+        b.iter(|| ServerInfo::try_from(black_box(info.as_slice())))
+    });
+    c.bench_function("players", |b| {
+        b.iter(|| Players::try_from(black_box(players.as_slice())))
+    });
+    c.bench_function("rules", |b| {
+        b.iter(|| Rules::try_from(black_box(rules.as_slice())))
+    });
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
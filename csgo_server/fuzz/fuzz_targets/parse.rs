@@ -0,0 +1,17 @@
+//! Throw arbitrary packets at every response parser, none of them may panic.
+//! Run with `cargo fuzz run parse` from `csgo_server`
+
+#![no_main]
+
+use csgo_server::info::ServerInfo;
+use csgo_server::master::decode_response;
+use csgo_server::players::Players;
+use csgo_server::rules::Rules;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    _ = ServerInfo::try_from(data);
+    _ = Players::try_from(data);
+    _ = Rules::try_from(data);
+    _ = decode_response(data);
+});
@@ -0,0 +1,76 @@
+use std::fmt;
+use std::io;
+use std::str::Utf8Error;
+use tokio::time::error::Elapsed;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The server didn't answer in time
+    Timeout,
+    /// The response ended before every field could be read
+    Truncated,
+    /// The response started with an unexpected header byte
+    BadHeader(u8),
+    /// A string field wasn't valid UTF-8
+    InvalidUtf8(Utf8Error),
+    /// The server kept answering with a new challenge
+    ChallengeLoopExceeded,
+    /// The server rejected the RCON password
+    AuthFailed,
+    /// The response was otherwise malformed, such as a broken split packet
+    Malformed(&'static str),
+    /// Socket error
+    Io(io::Error),
+}
+
+impl Error {
+    /// Whether this error means the server couldn't be reached at all,
+    /// as opposed to the server answering with something we don't understand
+    #[must_use]
+    pub fn is_unreachable(&self) -> bool {
+        matches!(self, Error::Timeout | Error::Io(_))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Timeout => write!(f, "Request timed out"),
+            Error::Truncated => write!(f, "Unexpected end of packet"),
+            Error::BadHeader(h) => write!(f, "Unexpected response header {h:#04x}"),
+            Error::InvalidUtf8(e) => write!(f, "Invalid UTF-8 in response: {e}"),
+            Error::ChallengeLoopExceeded => write!(f, "Too many challenge responses"),
+            Error::AuthFailed => write!(f, "RCON authentication failed"),
+            Error::Malformed(msg) => write!(f, "Malformed response: {msg}"),
+            Error::Io(e) => write!(f, "I/O error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::InvalidUtf8(e) => Some(e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<Elapsed> for Error {
+    fn from(_: Elapsed) -> Self {
+        Error::Timeout
+    }
+}
+
+impl From<Utf8Error> for Error {
+    fn from(e: Utf8Error) -> Self {
+        Error::InvalidUtf8(e)
+    }
+}
@@ -1,15 +1,11 @@
-use crate::byte;
-use crate::long_long;
-use crate::short;
-use crate::string;
+use crate::reader::Reader;
+use crate::writer::Writer;
 use serde::Serialize;
-use std::io::Bytes;
-use std::io::{self, Read};
 use tokio::net::UdpSocket;
 
-use crate::request::{send_request, Query};
+use crate::request::{send_request, Query, QueryOptions};
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum ServerType {
     Dedicated,
     NonDedicated,
@@ -20,15 +16,26 @@ pub enum ServerType {
 impl From<u8> for ServerType {
     fn from(i: u8) -> Self {
         match i {
-            b'd' => Self::Dedicated,
-            b'l' => Self::NonDedicated,
-            b'p' => Self::Proxy,
+            b'd' | b'D' => Self::Dedicated,
+            b'l' | b'L' => Self::NonDedicated,
+            b'p' | b'P' => Self::Proxy,
             _ => Self::Invalid,
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
+impl From<&ServerType> for u8 {
+    fn from(t: &ServerType) -> Self {
+        match t {
+            ServerType::Dedicated => b'd',
+            ServerType::NonDedicated => b'l',
+            ServerType::Proxy => b'p',
+            ServerType::Invalid => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum ServerEnvironment {
     Linux,
     Windows,
@@ -39,14 +46,25 @@ pub enum ServerEnvironment {
 impl From<u8> for ServerEnvironment {
     fn from(i: u8) -> Self {
         match i {
-            b'l' => Self::Linux,
-            b'w' => Self::Windows,
+            b'l' | b'L' => Self::Linux,
+            b'w' | b'W' => Self::Windows,
             b'm' | b'o' => Self::Mac,
             _ => Self::Invalid,
         }
     }
 }
 
+impl From<&ServerEnvironment> for u8 {
+    fn from(e: &ServerEnvironment) -> Self {
+        match e {
+            ServerEnvironment::Linux => b'l',
+            ServerEnvironment::Windows => b'w',
+            ServerEnvironment::Mac => b'm',
+            ServerEnvironment::Invalid => 0,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub enum ServerVisibility {
     Public,
@@ -81,20 +99,50 @@ impl From<u8> for VAC {
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct SourceTV {
-    pub port: i16,
+    pub port: u16,
     pub name: Box<str>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+/// Extra fields sent by servers running The Ship
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TheShip {
+    pub mode: u8,
+    pub witnesses: u8,
+    pub duration: u8,
+}
+
+/// Mod information of an obsolete GoldSrc response
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GoldSrcMod {
+    pub link: Box<str>,
+    pub download_link: Box<str>,
+    pub version: i32,
+    pub size: i32,
+    pub multiplayer_only: bool,
+    pub custom_dll: bool,
+}
+
+/// Fields only present in the obsolete GoldSrc (`m`) response
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GoldSrc {
+    pub address: Box<str>,
+    pub mod_info: Option<GoldSrcMod>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct ServerInfo {
     pub protocol: u8,
+    /// Decoded lossily, see `raw_name` for the name as sent by the server
     pub name: Box<str>,
+    #[serde(skip)]
+    pub raw_name: Box<[u8]>,
     pub map: Box<str>,
     pub folder: Box<str>,
     pub game: Box<str>,
-    pub id: i16,
+    /// Always 0 for GoldSrc responses
+    pub id: u16,
     pub players: u8,
     pub max_players: u8,
     pub bots: u8,
@@ -102,83 +150,118 @@ pub struct ServerInfo {
     pub server_environment: ServerEnvironment,
     pub public: bool,
     pub vac: bool,
+    pub the_ship: Option<TheShip>,
     pub version: Box<str>,
     pub edf: u8,
-    pub port: Option<i16>,
+    pub port: Option<u16>,
     pub steam_id: Option<u64>,
     pub source_tv: Option<SourceTV>,
     pub keywords: Option<Box<str>>,
     pub game_id: Option<u64>,
+    /// `Some` if the server answered with the obsolete GoldSrc response
+    pub goldsrc: Option<GoldSrc>,
 }
 
 use crate::Error;
 
-impl TryFrom<Bytes<&[u8]>> for ServerInfo {
+/// Extra Data Flags, marking which optional fields follow the version string
+const EDF_PORT: u8 = 0x80;
+const EDF_STEAM_ID: u8 = 0x10;
+const EDF_SOURCE_TV: u8 = 0x40;
+const EDF_KEYWORDS: u8 = 0x20;
+const EDF_GAME_ID: u8 = 0x01;
+
+/// App ids of The Ship, which sends extra fields after `vac`
+pub const THE_SHIP: &[u16] = &[2400, 2401, 2402, 2412];
+
+impl TryFrom<&[u8]> for ServerInfo {
     type Error = Error;
 
-    fn try_from(mut data: Bytes<&[u8]>) -> Result<Self, Error> {
-        data.advance_by(5).or(Err("Unable to advance by 5"))?;
+    fn try_from(data: &[u8]) -> Result<Self, Error> {
+        let mut data = Reader::new(data);
+        data.skip(4)?;
+
+        match data.byte()? {
+            b'I' => Self::source(&mut data),
+            b'm' => Self::goldsrc(&mut data),
+            h => Err(Error::BadHeader(h)),
+        }
+    }
+}
+
+impl ServerInfo {
+    fn source(data: &mut Reader<'_>) -> Result<Self, Error> {
+        let protocol: u8 = data.byte()?;
 
-        let protocol: u8 = byte(&mut data)?;
+        let (name, raw_name) = data.string_lossy()?;
+        let map = Box::from(data.string()?);
+        let folder = Box::from(data.string()?);
+        let game = Box::from(data.string()?);
 
-        let name = string(&mut data)?;
-        let map = string(&mut data)?;
-        let folder = string(&mut data)?;
-        let game = string(&mut data)?;
+        let id: u16 = data.ushort()?;
 
-        let id: i16 = short(&mut data)?;
+        let players: u8 = data.byte()?;
+        let max_players: u8 = data.byte()?;
+        let bots: u8 = data.byte()?;
 
-        let players: u8 = byte(&mut data)?;
-        let max_players: u8 = byte(&mut data)?;
-        let bots: u8 = byte(&mut data)?;
+        let server_type: ServerType = data.byte()?.into();
 
-        let server_type: ServerType = byte(&mut data)?.into();
+        let server_environment: ServerEnvironment = data.byte()?.into();
 
-        let server_environment: ServerEnvironment = byte(&mut data)?.into();
+        let public: bool = data.byte()? == 0;
+        let vac: bool = data.byte()? == 1;
 
-        let public: bool = byte(&mut data)? == 0;
-        let vac: bool = byte(&mut data)? == 1;
+        let the_ship: Option<TheShip> = if THE_SHIP.contains(&id) {
+            Some(TheShip {
+                mode: data.byte()?,
+                witnesses: data.byte()?,
+                duration: data.byte()?,
+            })
+        } else {
+            None
+        };
 
-        let version = string(&mut data)?;
+        let version = Box::from(data.string()?);
 
-        let edf: u8 = byte(&mut data)?;
+        let edf: u8 = data.byte()?;
 
-        let port: Option<i16> = if (edf & 0x80) != 0 {
-            Some(short(&mut data)?)
+        let port: Option<u16> = if (edf & EDF_PORT) != 0 {
+            Some(data.ushort()?)
         } else {
             None
         };
 
-        let steam_id: Option<u64> = if (edf & 0x10) != 0 {
-            Some(long_long(&mut data)?)
+        let steam_id: Option<u64> = if (edf & EDF_STEAM_ID) != 0 {
+            Some(data.long_long()?)
         } else {
             None
         };
 
-        let source_tv: Option<SourceTV> = if (edf & 0x40) != 0 {
-            let port = short(&mut data)?;
-            let name = string(&mut data)?;
+        let source_tv: Option<SourceTV> = if (edf & EDF_SOURCE_TV) != 0 {
+            let port = data.ushort()?;
+            let name = Box::from(data.string()?);
 
             Some(SourceTV { port, name })
         } else {
             None
         };
 
-        let keywords: Option<Box<str>> = if (edf & 0x20) != 0 {
-            Some(string(&mut data)?)
+        let keywords: Option<Box<str>> = if (edf & EDF_KEYWORDS) != 0 {
+            Some(Box::from(data.string()?))
         } else {
             None
         };
 
-        let game_id: Option<u64> = if (edf & 0x10) != 0 {
-            Some(long_long(&mut data)?)
+        let game_id: Option<u64> = if (edf & EDF_GAME_ID) != 0 {
+            Some(data.long_long()?)
         } else {
             None
         };
 
         Ok(ServerInfo {
             protocol,
-            name,
+            name: Box::from(name),
+            raw_name: Box::from(raw_name),
             map,
             folder,
             game,
@@ -190,6 +273,7 @@ impl TryFrom<Bytes<&[u8]>> for ServerInfo {
             server_environment,
             public,
             vac,
+            the_ship,
             version,
             edf,
             port,
@@ -197,13 +281,371 @@ impl TryFrom<Bytes<&[u8]>> for ServerInfo {
             source_tv,
             keywords,
             game_id,
+            goldsrc: None,
         })
     }
+
+    fn goldsrc(data: &mut Reader<'_>) -> Result<Self, Error> {
+        let address = Box::from(data.string()?);
+
+        let (name, raw_name) = data.string_lossy()?;
+        let map = Box::from(data.string()?);
+        let folder = Box::from(data.string()?);
+        let game = Box::from(data.string()?);
+
+        let players: u8 = data.byte()?;
+        let max_players: u8 = data.byte()?;
+        let protocol: u8 = data.byte()?;
+
+        let server_type: ServerType = data.byte()?.into();
+        let server_environment: ServerEnvironment = data.byte()?.into();
+
+        let public: bool = data.byte()? == 0;
+
+        let mod_info: Option<GoldSrcMod> = if data.byte()? == 1 {
+            let link = Box::from(data.string()?);
+            let download_link = Box::from(data.string()?);
+            data.skip(1)?;
+
+            Some(GoldSrcMod {
+                link,
+                download_link,
+                version: data.long()?,
+                size: data.long()?,
+                multiplayer_only: data.byte()? == 1,
+                custom_dll: data.byte()? == 1,
+            })
+        } else {
+            None
+        };
+
+        let vac: bool = data.byte()? == 1;
+        let bots: u8 = data.byte()?;
+
+        Ok(ServerInfo {
+            protocol,
+            name: Box::from(name),
+            raw_name: Box::from(raw_name),
+            map,
+            folder,
+            game,
+            id: 0,
+            players,
+            max_players,
+            bots,
+            server_type,
+            server_environment,
+            public,
+            vac,
+            the_ship: None,
+            version: Box::from(""),
+            edf: 0,
+            port: None,
+            steam_id: None,
+            source_tv: None,
+            keywords: None,
+            game_id: None,
+            goldsrc: Some(GoldSrc { address, mod_info }),
+        })
+    }
+
+    /// Encode as an `A2S_INFO` response, the inverse of `ServerInfo::try_from`.
+    /// The optional field flags of `edf` are taken from which fields are set,
+    /// servers with `goldsrc` set are encoded as the obsolete GoldSrc response
+    #[must_use]
+    pub fn encode(&self) -> Vec<u8> {
+        if let Some(goldsrc) = &self.goldsrc {
+            return self.encode_goldsrc(goldsrc);
+        }
+
+        let mut edf =
+            self.edf & !(EDF_PORT | EDF_STEAM_ID | EDF_SOURCE_TV | EDF_KEYWORDS | EDF_GAME_ID);
+        if self.port.is_some() {
+            edf |= EDF_PORT;
+        }
+        if self.steam_id.is_some() {
+            edf |= EDF_STEAM_ID;
+        }
+        if self.source_tv.is_some() {
+            edf |= EDF_SOURCE_TV;
+        }
+        if self.keywords.is_some() {
+            edf |= EDF_KEYWORDS;
+        }
+        if self.game_id.is_some() {
+            edf |= EDF_GAME_ID;
+        }
+
+        let mut w = Writer::new();
+        w.header(b'I')
+            .byte(self.protocol)
+            .string(&self.raw_name)
+            .string(self.map.as_bytes())
+            .string(self.folder.as_bytes())
+            .string(self.game.as_bytes())
+            .ushort(self.id)
+            .byte(self.players)
+            .byte(self.max_players)
+            .byte(self.bots)
+            .byte((&self.server_type).into())
+            .byte((&self.server_environment).into())
+            .byte(if self.public { 0 } else { 1 })
+            .byte(if self.vac { 1 } else { 0 });
+
+        if let Some(ship) = &self.the_ship {
+            w.byte(ship.mode).byte(ship.witnesses).byte(ship.duration);
+        }
+
+        w.string(self.version.as_bytes()).byte(edf);
+
+        if let Some(port) = self.port {
+            w.ushort(port);
+        }
+        if let Some(steam_id) = self.steam_id {
+            w.long_long(steam_id);
+        }
+        if let Some(stv) = &self.source_tv {
+            w.ushort(stv.port).string(stv.name.as_bytes());
+        }
+        if let Some(keywords) = &self.keywords {
+            w.string(keywords.as_bytes());
+        }
+        if let Some(game_id) = self.game_id {
+            w.long_long(game_id);
+        }
+
+        w.into_inner()
+    }
+
+    fn encode_goldsrc(&self, goldsrc: &GoldSrc) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.header(b'm')
+            .string(goldsrc.address.as_bytes())
+            .string(&self.raw_name)
+            .string(self.map.as_bytes())
+            .string(self.folder.as_bytes())
+            .string(self.game.as_bytes())
+            .byte(self.players)
+            .byte(self.max_players)
+            .byte(self.protocol)
+            .byte(u8::from(&self.server_type).to_ascii_uppercase())
+            .byte(u8::from(&self.server_environment).to_ascii_uppercase())
+            .byte(if self.public { 0 } else { 1 });
+
+        match &goldsrc.mod_info {
+            Some(m) => {
+                w.byte(1)
+                    .string(m.link.as_bytes())
+                    .string(m.download_link.as_bytes())
+                    .byte(0)
+                    .long(m.version)
+                    .long(m.size)
+                    .byte(if m.multiplayer_only { 1 } else { 0 })
+                    .byte(if m.custom_dll { 1 } else { 0 });
+            }
+            None => {
+                w.byte(0);
+            }
+        }
+
+        w.byte(if self.vac { 1 } else { 0 }).byte(self.bots);
+
+        w.into_inner()
+    }
+}
+
+pub async fn get_server_info(
+    sock: &UdpSocket,
+    options: &QueryOptions,
+) -> Result<ServerInfo, Error> {
+    let data = send_request(sock, Query::Info, options).await?;
+
+    ServerInfo::try_from(data.as_slice())
 }
 
-pub async fn get_server_info(sock: &UdpSocket) -> io::Result<ServerInfo> {
-    let data = send_request(sock, Query::Info).await?;
-    let bytes = data.bytes();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn server_info() -> ServerInfo {
+        ServerInfo {
+            protocol: 17,
+            name: "meow".into(),
+            raw_name: Box::from(&b"meow"[..]),
+            map: "dz_sirocco".into(),
+            folder: "csgo".into(),
+            game: "Counter-Strike: Global Offensive".into(),
+            id: 730,
+            players: 12,
+            max_players: 18,
+            bots: 0,
+            server_type: ServerType::Dedicated,
+            server_environment: ServerEnvironment::Linux,
+            public: true,
+            vac: true,
+            the_ship: None,
+            version: "1.38.8.1".into(),
+            edf: 0,
+            port: None,
+            steam_id: None,
+            source_tv: None,
+            keywords: None,
+            game_id: None,
+            goldsrc: None,
+        }
+    }
+
+    #[test]
+    fn game_id_flag() {
+        let info = ServerInfo {
+            game_id: Some(730),
+            ..server_info()
+        };
+
+        let parsed = ServerInfo::try_from(info.encode().as_slice()).unwrap();
 
-    ServerInfo::try_from(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        assert_eq!(parsed.edf, EDF_GAME_ID);
+        assert_eq!(parsed.steam_id, None);
+        assert_eq!(parsed.game_id, Some(730));
+    }
+
+    #[test]
+    fn high_port() {
+        let info = ServerInfo {
+            port: Some(40000),
+            source_tv: Some(SourceTV {
+                port: 40001,
+                name: "GOTV".into(),
+            }),
+            ..server_info()
+        };
+
+        let parsed = ServerInfo::try_from(info.encode().as_slice()).unwrap();
+
+        assert_eq!(parsed.port, Some(40000));
+        assert_eq!(parsed.source_tv.unwrap().port, 40001);
+    }
+
+    #[test]
+    fn the_ship() {
+        let info = ServerInfo {
+            id: 2400,
+            the_ship: Some(TheShip {
+                mode: 1,
+                witnesses: 2,
+                duration: 3,
+            }),
+            ..server_info()
+        };
+
+        let parsed = ServerInfo::try_from(info.encode().as_slice()).unwrap();
+
+        assert_eq!(parsed, info);
+    }
+
+    #[test]
+    fn goldsrc() {
+        let info = ServerInfo {
+            id: 0,
+            version: "".into(),
+            goldsrc: Some(GoldSrc {
+                address: "127.0.0.1:27015".into(),
+                mod_info: Some(GoldSrcMod {
+                    link: "https://example.com".into(),
+                    download_link: "".into(),
+                    version: 1,
+                    size: 1024,
+                    multiplayer_only: true,
+                    custom_dll: true,
+                }),
+            }),
+            ..server_info()
+        };
+
+        let encoded = info.encode();
+        assert_eq!(encoded[4], b'm');
+
+        let parsed = ServerInfo::try_from(encoded.as_slice()).unwrap();
+
+        assert_eq!(parsed, info);
+    }
+
+    fn string() -> impl Strategy<Value = Box<str>> {
+        "[^\0]{0,32}".prop_map(Box::from)
+    }
+
+    prop_compose! {
+        fn arb_server_info()(
+            protocol: u8,
+            name in string(),
+            map in string(),
+            folder in string(),
+            game in string(),
+            id in any::<u16>().prop_filter("The Ship", |id| !THE_SHIP.contains(id)),
+            players: u8,
+            max_players: u8,
+            bots: u8,
+            server_type in prop_oneof![
+                Just(ServerType::Dedicated),
+                Just(ServerType::NonDedicated),
+                Just(ServerType::Proxy),
+            ],
+            server_environment in prop_oneof![
+                Just(ServerEnvironment::Linux),
+                Just(ServerEnvironment::Windows),
+                Just(ServerEnvironment::Mac),
+            ],
+            public: bool,
+            vac: bool,
+            version in string(),
+            port: Option<u16>,
+            steam_id: Option<u64>,
+            source_tv in proptest::option::of((any::<u16>(), string())),
+            keywords in proptest::option::of(string()),
+            game_id: Option<u64>,
+        ) -> ServerInfo {
+            ServerInfo {
+                protocol,
+                raw_name: Box::from(name.as_bytes()),
+                name,
+                map,
+                folder,
+                game,
+                id,
+                players,
+                max_players,
+                bots,
+                server_type,
+                server_environment,
+                public,
+                vac,
+                the_ship: None,
+                version,
+                edf: 0,
+                port,
+                steam_id,
+                source_tv: source_tv.map(|(port, name)| SourceTV { port, name }),
+                keywords,
+                game_id,
+                goldsrc: None,
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip(info in arb_server_info()) {
+            let encoded = info.encode();
+            let parsed = ServerInfo::try_from(encoded.as_slice()).unwrap();
+
+            prop_assert_eq!(&parsed, &ServerInfo { edf: parsed.edf, ..info });
+            prop_assert_eq!(parsed.encode(), encoded);
+        }
+
+        #[test]
+        fn never_panics(data: Vec<u8>) {
+            _ = ServerInfo::try_from(data.as_slice());
+        }
+    }
 }
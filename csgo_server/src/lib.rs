@@ -1,57 +1,13 @@
-#![feature(iter_next_chunk)]
-#![feature(iter_advance_by)]
-use std::io::Bytes;
-
-pub type Error = Box<dyn std::error::Error + Send + Sync>;
+mod error;
+pub use error::Error;
 
 pub mod info;
+pub mod master;
 pub mod players;
+pub mod rcon;
+pub mod reader;
 pub mod request;
-
-pub(crate) fn byte(data: &mut Bytes<&[u8]>) -> Result<u8, Error> {
-    Ok(data.next().transpose()?.ok_or("Unexpected end of file")?)
-}
-
-pub(crate) fn short(data: &mut Bytes<&[u8]>) -> Result<i16, Error> {
-    Ok(i16::from_le_bytes(
-        data.take(2)
-            .collect::<Result<Vec<u8>, std::io::Error>>()?
-            .as_slice()
-            .try_into()?,
-    ))
-}
-
-pub(crate) fn long(data: &mut Bytes<&[u8]>) -> Result<i32, Error> {
-    Ok(i32::from_le_bytes(
-        data.take(4)
-            .collect::<Result<Vec<u8>, std::io::Error>>()?
-            .as_slice()
-            .try_into()?,
-    ))
-}
-
-pub(crate) fn float(data: &mut Bytes<&[u8]>) -> Result<f32, Error> {
-    Ok(f32::from_le_bytes(
-        data.take(4)
-            .collect::<Result<Vec<u8>, std::io::Error>>()?
-            .as_slice()
-            .try_into()?,
-    ))
-}
-
-pub(crate) fn long_long(data: &mut Bytes<&[u8]>) -> Result<u64, Error> {
-    Ok(u64::from_le_bytes(
-        data.take(8)
-            .collect::<Result<Vec<u8>, std::io::Error>>()?
-            .as_slice()
-            .try_into()?,
-    ))
-}
-
-pub(crate) fn string(data: &mut Bytes<&[u8]>) -> Result<Box<str>, Error> {
-    let content: Vec<u8> = data
-        .take_while(|b| b.as_ref().map(|b| *b != 0).unwrap_or(false))
-        .collect::<Result<Vec<u8>, std::io::Error>>()?;
-
-    Ok(Box::from(std::str::from_utf8(&content)?))
-}
+pub mod rules;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod writer;
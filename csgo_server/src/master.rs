@@ -0,0 +1,187 @@
+//! Steam master server query protocol, see
+//! <https://developer.valvesoftware.com/wiki/Master_Server_Query_Protocol>
+
+use crate::Error;
+use crate::reader::Reader;
+use crate::request::QueryOptions;
+use crate::writer::Writer;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use tokio::net::UdpSocket;
+use tokio::time;
+
+/// Valve's master server for Source games
+pub const STEAM_MASTER_SERVER: &str = "hl2master.steampowered.com:27011";
+
+/// Give up after this many pages, every page holds about 230 servers
+const MAX_PAGES: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum Region {
+    UsEast = 0x00,
+    UsWest = 0x01,
+    SouthAmerica = 0x02,
+    Europe = 0x03,
+    Asia = 0x04,
+    Australia = 0x05,
+    MiddleEast = 0x06,
+    Africa = 0x07,
+    World = 0xFF,
+}
+
+/// A master server, Valve's by default, any address speaking the same protocol works
+#[derive(Debug, Clone)]
+pub struct MasterServer {
+    pub addr: String,
+}
+
+impl Default for MasterServer {
+    fn default() -> Self {
+        MasterServer {
+            addr: STEAM_MASTER_SERVER.into(),
+        }
+    }
+}
+
+const EMPTY: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0);
+
+/// Encode a master server query starting after `seed`
+#[must_use]
+pub fn encode_query(region: Region, seed: SocketAddrV4, filter: &str) -> Vec<u8> {
+    let mut w = Writer::new();
+    w.byte(0x31)
+        .byte(region as u8)
+        .string(seed.to_string().as_bytes())
+        .string(filter.as_bytes());
+
+    w.into_inner()
+}
+
+/// Decode a page of server addresses, ports are big endian unlike everything else
+pub fn decode_response(data: &[u8]) -> Result<Vec<SocketAddrV4>, Error> {
+    let mut data = Reader::new(data);
+    data.header(0x66)?;
+
+    match data.byte()? {
+        0x0A => (),
+        h => return Err(Error::BadHeader(h)),
+    }
+
+    let mut servers = Vec::new();
+    while !data.remaining().is_empty() {
+        let ip: [u8; 4] = data.bytes(4)?.try_into().unwrap();
+        let port = u16::from_be_bytes(data.bytes(2)?.try_into().unwrap());
+
+        servers.push(SocketAddrV4::new(Ipv4Addr::from(ip), port));
+    }
+
+    Ok(servers)
+}
+
+/// Encode a page of server addresses, the inverse of `decode_response`
+#[must_use]
+pub fn encode_response(servers: &[SocketAddrV4]) -> Vec<u8> {
+    let mut w = Writer::new();
+    w.header(0x66).byte(0x0A);
+
+    for addr in servers {
+        w.bytes(&addr.ip().octets())
+            .bytes(&addr.port().to_be_bytes());
+    }
+
+    w.into_inner()
+}
+
+impl MasterServer {
+    #[must_use]
+    pub fn new(addr: &str) -> Self {
+        MasterServer { addr: addr.into() }
+    }
+
+    /// List servers matching `filter`, such as `\gamedir\csgo\map\dz_*`.
+    /// `options.deadline` and `options.timeout` apply to each page
+    pub async fn servers(
+        &self,
+        region: Region,
+        filter: &str,
+        options: &QueryOptions,
+    ) -> Result<Vec<SocketAddrV4>, Error> {
+        let sock = UdpSocket::bind("0.0.0.0:0").await?;
+        time::timeout(options.timeout, sock.connect(&self.addr)).await??;
+
+        let mut servers: Vec<SocketAddrV4> = Vec::new();
+        let mut seed = EMPTY;
+        let mut buf = [0; 4096];
+
+        for _ in 0..MAX_PAGES {
+            let query = encode_query(region, seed, filter);
+
+            let page = time::timeout(options.deadline, async {
+                let mut retries = 0;
+
+                loop {
+                    time::timeout(options.timeout, sock.send(&query)).await??;
+
+                    match time::timeout(options.timeout, sock.recv(&mut buf)).await {
+                        Ok(len) => return decode_response(&buf[..len?]),
+                        Err(_) if retries < options.retries => retries += 1,
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+            })
+            .await??;
+
+            // The last page ends with 0.0.0.0:0
+            let done = page.last().is_none_or(|a| *a == EMPTY);
+
+            if let Some(last) = page.last() {
+                seed = *last;
+            }
+
+            servers.extend(page.into_iter().filter(|a| *a != EMPTY));
+
+            if done {
+                break;
+            }
+        }
+
+        Ok(servers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeMaster;
+
+    #[tokio::test]
+    async fn pages() {
+        let servers: Vec<SocketAddrV4> = (0..10)
+            .map(|i| SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, i), 27015 + i as u16))
+            .collect();
+
+        let fake = FakeMaster::start(servers.clone(), 3).await.unwrap();
+        let master = MasterServer::new(&fake.addr.to_string());
+
+        let found = master
+            .servers(
+                Region::World,
+                r"\gamedir\csgo\map\dz_*",
+                &QueryOptions::default(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(found, servers);
+    }
+
+    #[test]
+    fn round_trip() {
+        let servers = [SocketAddrV4::new(Ipv4Addr::new(1, 2, 3, 4), 27015), EMPTY];
+
+        assert_eq!(
+            decode_response(&encode_response(&servers)).unwrap(),
+            servers
+        );
+    }
+}
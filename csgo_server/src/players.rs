@@ -1,54 +1,74 @@
-use crate::byte;
-use crate::float;
-use crate::long;
+use crate::info::THE_SHIP;
+use crate::reader::Reader;
+use crate::writer::Writer;
 use serde::Serialize;
-use std::io;
-use std::io::Bytes;
-use std::io::Read;
 use tokio::net::UdpSocket;
 
 use crate::{
-    request::{send_request, Query},
-    string, Error,
+    request::{send_request, Query, QueryOptions},
+    Error,
 };
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Player {
     pub index: u8,
+    /// Decoded lossily, see `raw_name` for the name as sent by the server
     pub name: Box<str>,
+    #[serde(skip)]
+    pub raw_name: Box<[u8]>,
     pub score: i32,
     pub duration: f32,
+    pub the_ship: Option<TheShipPlayer>,
 }
 
-impl TryFrom<&mut Bytes<&[u8]>> for Player {
+/// Extra player fields sent by servers running The Ship
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TheShipPlayer {
+    pub deaths: i32,
+    pub money: i32,
+}
+
+impl TryFrom<&mut Reader<'_>> for Player {
     type Error = Error;
 
-    fn try_from(data: &mut Bytes<&[u8]>) -> Result<Player, Error> {
-        let index = byte(data)?;
-        let name = string(data)?;
+    fn try_from(data: &mut Reader<'_>) -> Result<Player, Error> {
+        let index = data.byte()?;
+        let (name, raw_name) = data.string_lossy()?;
 
-        let score = long(data)?;
-        let duration = float(data)?;
+        let score = data.long()?;
+        let duration = data.float()?;
 
         Ok(Player {
             index,
-            name,
+            name: Box::from(name),
+            raw_name: Box::from(raw_name),
             score,
             duration,
+            the_ship: None,
         })
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Players(pub Vec<Player>);
 
-impl TryFrom<Bytes<&[u8]>> for Players {
+impl TryFrom<&[u8]> for Players {
     type Error = Error;
 
-    fn try_from(mut data: Bytes<&[u8]>) -> Result<Self, Error> {
-        data.advance_by(5).or(Err("Unable to advance by 5"))?;
+    /// Parse the regular Source layout, see `Players::parse` for servers running The Ship
+    fn try_from(data: &[u8]) -> Result<Self, Error> {
+        Players::parse(data, 0)
+    }
+}
 
-        let num_players: u8 = data.next().transpose()?.ok_or("Unexpected end of file")?;
+impl Players {
+    /// Parse an `A2S_PLAYER` response of a server running the game `app_id`, as sent in its
+    /// `A2S_INFO` response
+    pub fn parse(data: &[u8], app_id: u16) -> Result<Self, Error> {
+        let mut data = Reader::new(data);
+        data.header(b'D')?;
+
+        let num_players: u8 = data.byte()?;
 
         let mut players: Vec<Player> = Vec::new();
 
@@ -57,11 +77,39 @@ impl TryFrom<Bytes<&[u8]>> for Players {
             players.push(p);
         }
 
+        // The Ship appends deaths and money for every player after the regular list
+        if THE_SHIP.contains(&app_id) {
+            for p in players.iter_mut() {
+                p.the_ship = Some(TheShipPlayer {
+                    deaths: data.long()?,
+                    money: data.long()?,
+                });
+            }
+        }
+
         Ok(Players(players))
     }
-}
 
-impl Players {
+    /// Encode as an `A2S_PLAYER` response, the inverse of `Players::parse`
+    #[must_use]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.header(b'D').byte(self.0.len() as u8);
+
+        for p in &self.0 {
+            w.byte(p.index)
+                .string(&p.raw_name)
+                .long(p.score)
+                .float(p.duration);
+        }
+
+        for ship in self.0.iter().filter_map(|p| p.the_ship.as_ref()) {
+            w.long(ship.deaths).long(ship.money);
+        }
+
+        w.into_inner()
+    }
+
     #[must_use]
     pub fn real(self) -> Self {
         Players(
@@ -73,33 +121,84 @@ impl Players {
     }
 }
 
-pub async fn get_players(sock: &UdpSocket) -> io::Result<Players> {
-    let data = send_request(sock, Query::Player).await?;
-    let bytes = data.bytes();
+/// `app_id` is the game the server runs, from its `ServerInfo`
+pub async fn get_players(
+    sock: &UdpSocket,
+    app_id: u16,
+    options: &QueryOptions,
+) -> Result<Players, Error> {
+    let data = send_request(sock, Query::Player, options).await?;
 
-    Players::try_from(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    Players::parse(data.as_slice(), app_id)
 }
 
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-
-//     #[test]
-//     fn yea() {
-//         let data = [
-//             255, 255, 255, 255, 68, 8, 0, 68, 97, 116, 72, 111, 115, 116, 32, 45, 32, 71, 79, 84,
-//             86, 0, 0, 0, 0, 0, 204, 97, 175, 70, 0, 0, 0, 0, 0, 0, 21, 145, 89, 70, 0, 112, 111,
-//             107, 105, 115, 52, 52, 52, 118, 50, 0, 29, 0, 0, 0, 199, 245, 219, 69, 0, 78, 111, 109,
-//             97, 100, 0, 36, 0, 0, 0, 38, 198, 218, 69, 0, 71, 108, 97, 109, 117, 82, 0, 37, 0, 0,
-//             0, 245, 204, 101, 69, 0, 36, 116, 105, 108, 108, 80, 97, 108, 109, 84, 114, 101, 101,
-//             115, 226, 132, 162, 0, 0, 0, 0, 0, 143, 38, 193, 67, 0, 67, 114, 97, 115, 104, 101,
-//             114, 0, 0, 0, 0, 0, 112, 127, 17, 67, 0, 67, 117, 112, 111, 102, 106, 117, 105, 99,
-//             101, 0, 0, 0, 0, 0, 114, 71, 17, 67,
-//         ]
-//         .to_vec();
-
-//         println!("{:#?}", Players::try_from(data));
-
-//         assert_eq!(1, 1);
-//     }
-// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn yea() {
+        let data = [
+            255, 255, 255, 255, 68, 8, 0, 68, 97, 116, 72, 111, 115, 116, 32, 45, 32, 71, 79, 84,
+            86, 0, 0, 0, 0, 0, 204, 97, 175, 70, 0, 0, 0, 0, 0, 0, 21, 145, 89, 70, 0, 112, 111,
+            107, 105, 115, 52, 52, 52, 118, 50, 0, 29, 0, 0, 0, 199, 245, 219, 69, 0, 78, 111, 109,
+            97, 100, 0, 36, 0, 0, 0, 38, 198, 218, 69, 0, 71, 108, 97, 109, 117, 82, 0, 37, 0, 0,
+            0, 245, 204, 101, 69, 0, 36, 116, 105, 108, 108, 80, 97, 108, 109, 84, 114, 101, 101,
+            115, 226, 132, 162, 0, 0, 0, 0, 0, 143, 38, 193, 67, 0, 67, 114, 97, 115, 104, 101,
+            114, 0, 0, 0, 0, 0, 112, 127, 17, 67, 0, 67, 117, 112, 111, 102, 106, 117, 105, 99,
+            101, 0, 0, 0, 0, 0, 114, 71, 17, 67,
+        ]
+        .to_vec();
+
+        let players = Players::try_from(data.as_slice()).unwrap();
+
+        assert_eq!(players.0.len(), 8);
+        assert_eq!(&*players.0[0].name, "DatHost - GOTV");
+        assert_eq!(&*players.0[5].name, "$tillPalmTrees™");
+        assert_eq!(players.real().0.len(), 6);
+    }
+
+    #[test]
+    fn invalid_utf8_name() {
+        let mut data = vec![255, 255, 255, 255, b'D', 2];
+        data.extend_from_slice(&[0, b'm', 0xFF, b'w', 0, 1, 0, 0, 0, 0, 0, 128, 63]);
+        data.extend_from_slice(&[1, b'c', b'a', b't', 0, 2, 0, 0, 0, 0, 0, 0, 64]);
+
+        let players = Players::try_from(data.as_slice()).unwrap();
+
+        assert_eq!(&*players.0[0].name, "m\u{FFFD}w");
+        assert_eq!(&*players.0[0].raw_name, &[b'm', 0xFF, b'w']);
+        assert_eq!(players.0[0].score, 1);
+        assert_eq!(&*players.0[1].name, "cat");
+        assert_eq!(players.0[1].duration, 2.0);
+        assert_eq!(players.0[1].the_ship, None);
+    }
+
+    #[test]
+    fn the_ship() {
+        let mut data = vec![255, 255, 255, 255, b'D', 1];
+        data.extend_from_slice(&[0, b'c', b'a', b't', 0, 1, 0, 0, 0, 0, 0, 128, 63]);
+        data.extend_from_slice(&[2, 0, 0, 0, 100, 0, 0, 0]);
+
+        let players = Players::parse(data.as_slice(), 2400).unwrap();
+
+        assert_eq!(
+            players.0[0].the_ship,
+            Some(TheShipPlayer {
+                deaths: 2,
+                money: 100
+            })
+        );
+        assert_eq!(players.encode(), data);
+
+        // the same bytes are only trailing junk for any other game
+        let players = Players::parse(data.as_slice(), 730).unwrap();
+        assert_eq!(players.0[0].the_ship, None);
+
+        data.truncate(data.len() - 8);
+        assert!(matches!(
+            Players::parse(data.as_slice(), 2400),
+            Err(Error::Truncated)
+        ));
+    }
+}
@@ -0,0 +1,177 @@
+//! Source RCON client, see <https://developer.valvesoftware.com/wiki/Source_RCON_Protocol>
+
+use crate::Error;
+use crate::reader::Reader;
+use crate::writer::Writer;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::time;
+use tokio::time::Duration;
+
+pub const SERVERDATA_AUTH: i32 = 3;
+pub const SERVERDATA_AUTH_RESPONSE: i32 = 2;
+pub const SERVERDATA_EXECCOMMAND: i32 = 2;
+pub const SERVERDATA_RESPONSE_VALUE: i32 = 0;
+
+/// Packets larger than this are rejected instead of allocated
+const MAX_PACKET_SIZE: usize = 1 << 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packet {
+    pub id: i32,
+    pub kind: i32,
+    pub body: Vec<u8>,
+}
+
+impl Packet {
+    #[must_use]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        // id + type + body + two null terminators
+        w.long((4 + 4 + self.body.len() + 2) as i32)
+            .long(self.id)
+            .long(self.kind)
+            .string(&self.body)
+            .byte(0);
+
+        w.into_inner()
+    }
+
+    pub async fn read<S: AsyncReadExt + Unpin>(stream: &mut S) -> Result<Self, Error> {
+        let size = stream.read_i32_le().await? as usize;
+        if !(10..=MAX_PACKET_SIZE).contains(&size) {
+            return Err(Error::Malformed("Invalid RCON packet size"));
+        }
+
+        let mut buf = vec![0; size];
+        stream.read_exact(&mut buf).await?;
+
+        let mut data = Reader::new(&buf);
+        let id = data.long()?;
+        let kind = data.long()?;
+        let body = data.raw_string()?.to_vec();
+
+        Ok(Packet { id, kind, body })
+    }
+}
+
+pub struct Rcon {
+    stream: TcpStream,
+    next_id: i32,
+    timeout: Duration,
+}
+
+impl Rcon {
+    /// Connect and authenticate, `timeout` applies to every step of every command
+    pub async fn connect<A: ToSocketAddrs>(
+        addr: A,
+        password: &str,
+        timeout: Duration,
+    ) -> Result<Self, Error> {
+        let stream = time::timeout(timeout, TcpStream::connect(addr)).await??;
+
+        let mut rcon = Rcon {
+            stream,
+            next_id: 1,
+            timeout,
+        };
+
+        let id = rcon.send(SERVERDATA_AUTH, password.as_bytes()).await?;
+
+        // The server sends an empty SERVERDATA_RESPONSE_VALUE before the auth response
+        loop {
+            let packet = rcon.recv().await?;
+
+            if packet.kind == SERVERDATA_AUTH_RESPONSE {
+                return match packet.id {
+                    -1 => Err(Error::AuthFailed),
+                    i if i == id => Ok(rcon),
+                    _ => Err(Error::Malformed("Unexpected RCON auth response id")),
+                };
+            }
+        }
+    }
+
+    async fn send(&mut self, kind: i32, body: &[u8]) -> Result<i32, Error> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+
+        let packet = Packet {
+            id,
+            kind,
+            body: body.to_vec(),
+        };
+
+        time::timeout(self.timeout, self.stream.write_all(&packet.encode())).await??;
+
+        Ok(id)
+    }
+
+    async fn recv(&mut self) -> Result<Packet, Error> {
+        time::timeout(self.timeout, Packet::read(&mut self.stream)).await?
+    }
+
+    /// Run a console command and return its output.
+    /// Long output is split over multiple packets, so an empty packet is sent after the command,
+    /// the server mirrors it once everything before it has been answered
+    pub async fn exec(&mut self, command: &str) -> Result<String, Error> {
+        let id = self
+            .send(SERVERDATA_EXECCOMMAND, command.as_bytes())
+            .await?;
+        let end = self.send(SERVERDATA_RESPONSE_VALUE, &[]).await?;
+
+        let mut output: Vec<u8> = Vec::new();
+
+        loop {
+            let packet = self.recv().await?;
+
+            match packet.id {
+                i if i == end => break,
+                i if i == id && packet.kind == SERVERDATA_RESPONSE_VALUE => {
+                    output.extend_from_slice(&packet.body)
+                }
+                // Leftovers from earlier commands
+                _ => (),
+            }
+        }
+
+        Ok(String::from_utf8_lossy(&output).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeRcon;
+
+    fn output(command: &str) -> String {
+        match command {
+            "status" => "hostname: meow\n".repeat(200),
+            c => format!("{c}\n"),
+        }
+    }
+
+    #[tokio::test]
+    async fn exec() {
+        let server = FakeRcon::start("hunter2", 1000, output).await.unwrap();
+        let mut rcon = Rcon::connect(server.addr, "hunter2", Duration::from_secs(1))
+            .await
+            .unwrap();
+
+        assert_eq!(rcon.exec("status").await.unwrap(), output("status"));
+        assert_eq!(
+            rcon.exec("changelevel dz_sirocco").await.unwrap(),
+            "changelevel dz_sirocco\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn wrong_password() {
+        let server = FakeRcon::start("hunter2", 1000, output).await.unwrap();
+
+        assert!(matches!(
+            Rcon::connect(server.addr, "hunter3", Duration::from_secs(1)).await,
+            Err(Error::AuthFailed)
+        ));
+    }
+}
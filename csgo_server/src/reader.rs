@@ -0,0 +1,96 @@
+use crate::Error;
+use std::borrow::Cow;
+
+/// Cursor over a response packet, every read advances past the value it returns
+#[derive(Debug, Clone)]
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    #[must_use]
+    pub fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    /// Bytes that haven't been read yet
+    #[must_use]
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.data[self.pos..]
+    }
+
+    pub fn bytes(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        let end = self.pos.checked_add(n).ok_or(Error::Truncated)?;
+        let bytes = self.data.get(self.pos..end).ok_or(Error::Truncated)?;
+        self.pos = end;
+
+        Ok(bytes)
+    }
+
+    pub fn skip(&mut self, n: usize) -> Result<(), Error> {
+        self.bytes(n).map(|_| ())
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        // bytes() always returns exactly N bytes
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    /// Skip the `FF FF FF FF` packet header and check the response type
+    pub fn header(&mut self, expected: u8) -> Result<(), Error> {
+        self.skip(4)?;
+
+        match self.byte()? {
+            h if h == expected => Ok(()),
+            h => Err(Error::BadHeader(h)),
+        }
+    }
+
+    pub fn byte(&mut self) -> Result<u8, Error> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    pub fn short(&mut self) -> Result<i16, Error> {
+        Ok(i16::from_le_bytes(self.array()?))
+    }
+
+    pub fn ushort(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub fn long(&mut self) -> Result<i32, Error> {
+        Ok(i32::from_le_bytes(self.array()?))
+    }
+
+    pub fn float(&mut self) -> Result<f32, Error> {
+        Ok(f32::from_le_bytes(self.array()?))
+    }
+
+    pub fn long_long(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    /// Null terminated string as raw bytes, without the terminator
+    pub fn raw_string(&mut self) -> Result<&'a [u8], Error> {
+        let rest = self.remaining();
+        let len = rest.iter().position(|b| *b == 0).ok_or(Error::Truncated)?;
+
+        self.pos += len + 1;
+
+        Ok(&rest[..len])
+    }
+
+    /// Null terminated string, borrowed from the packet
+    pub fn string(&mut self) -> Result<&'a str, Error> {
+        Ok(std::str::from_utf8(self.raw_string()?)?)
+    }
+
+    /// Null terminated string, invalid UTF-8 is replaced with `U+FFFD`.
+    /// Use this for anything user controlled, like player names
+    pub fn string_lossy(&mut self) -> Result<(Cow<'a, str>, &'a [u8]), Error> {
+        let raw = self.raw_string()?;
+
+        Ok((String::from_utf8_lossy(raw), raw))
+    }
+}
@@ -1,4 +1,8 @@
-use std::io;
+use crate::reader::Reader;
+use crate::Error;
+use bzip2::read::BzDecoder;
+use std::collections::BTreeMap;
+use std::io::Read;
 use tokio::net::UdpSocket;
 use tokio::time;
 use tokio::time::Duration;
@@ -9,6 +13,8 @@ pub enum Query {
     Info,
     /// `A2S_PLAYER`
     Player,
+    /// `A2S_RULES`
+    Rules,
 }
 
 impl Query {
@@ -17,13 +23,177 @@ impl Query {
         match self {
             Query::Info => "TSource Engine Query\0".as_bytes(),
             Query::Player => &[b'U', 0xFF, 0xFF, 0xFF, 0xFF],
+            Query::Rules => &[b'V', 0xFF, 0xFF, 0xFF, 0xFF],
         }
     }
 }
 
-pub(crate) async fn send_request(sock: &UdpSocket, query: Query) -> io::Result<[u8; 4096]> {
+/// Header of a response that fits into a single packet
+const SINGLE_PACKET: i32 = -1;
+/// Header of a response that is split into multiple packets
+const SPLIT_PACKET: i32 = -2;
+/// Set in the split packet id if the payload is bzip2 compressed
+const COMPRESSED: u32 = 0x8000_0000;
+/// Largest decompressed size a compressed response may claim, it's read from the network
+const MAX_DECOMPRESSED: u32 = 1 << 20;
+
+fn packet_header(data: &[u8]) -> Result<i32, Error> {
+    Reader::new(data).long()
+}
+
+/// One packet of a split response
+struct SplitPacket {
+    id: u32,
+    total: u8,
+    number: u8,
+    /// Only present on the first packet of a compressed response
+    /// (decompressed size, CRC32)
+    compression: Option<(u32, u32)>,
+    payload: Vec<u8>,
+}
+
+impl SplitPacket {
+    fn parse(data: &[u8]) -> Result<Self, Error> {
+        let mut data = Reader::new(data);
+        data.skip(4)?;
+
+        let id = data.long()? as u32;
+        let total = data.byte()?;
+        let number = data.byte()?;
+
+        // Source engine packets carry the maximum packet size here, which we don't need
+        data.skip(2)?;
+
+        let compression = if id & COMPRESSED != 0 && number == 0 {
+            let size = data.long()? as u32;
+            let crc = data.long()? as u32;
+
+            Some((size, crc))
+        } else {
+            None
+        };
+
+        let payload = data.remaining().to_vec();
+
+        Ok(SplitPacket {
+            id,
+            total,
+            number,
+            compression,
+            payload,
+        })
+    }
+}
+
+/// Receive a full response, reassembling it if the server split it over multiple packets
+async fn recv_response(sock: &UdpSocket, timeout: Duration) -> Result<Vec<u8>, Error> {
     let mut buf = [0; 4096];
-    let timeout = Duration::from_secs(10);
+
+    let len = time::timeout(timeout, sock.recv(&mut buf)).await??;
+    let first = &buf[..len];
+
+    match packet_header(first)? {
+        SINGLE_PACKET => return Ok(first.to_vec()),
+        SPLIT_PACKET => (),
+        _ => return Err(Error::Malformed("Invalid packet header")),
+    }
+
+    let first = SplitPacket::parse(first)?;
+    let id = first.id;
+    let total = first.total;
+
+    if total == 0 {
+        return Err(Error::Malformed("Split packet with no parts"));
+    }
+
+    let mut compression = first.compression;
+    let mut packets: BTreeMap<u8, Vec<u8>> = BTreeMap::new();
+    packets.insert(first.number, first.payload);
+
+    while packets.len() < total as usize {
+        let len = time::timeout(timeout, sock.recv(&mut buf)).await??;
+        let data = &buf[..len];
+
+        // Stray packets from an earlier, timed out request
+        if packet_header(data)? != SPLIT_PACKET {
+            continue;
+        }
+
+        let packet = SplitPacket::parse(data)?;
+        if packet.id != id {
+            continue;
+        }
+
+        if packet.number >= total {
+            return Err(Error::Malformed("Split packet number out of range"));
+        }
+
+        if packet.compression.is_some() {
+            compression = packet.compression;
+        }
+
+        packets.insert(packet.number, packet.payload);
+    }
+
+    let data: Vec<u8> = packets.into_values().flatten().collect();
+
+    if id & COMPRESSED == 0 {
+        return Ok(data);
+    }
+
+    let (size, crc) = compression.ok_or(Error::Malformed("Missing compression header"))?;
+
+    if size > MAX_DECOMPRESSED {
+        return Err(Error::Malformed("Decompressed size too large"));
+    }
+
+    let mut decompressed = Vec::with_capacity(size as usize);
+    BzDecoder::new(data.as_slice())
+        .take(size as u64)
+        .read_to_end(&mut decompressed)
+        .or(Err(Error::Malformed("Invalid bzip2 data")))?;
+
+    if decompressed.len() != size as usize {
+        return Err(Error::Malformed("Decompressed size mismatch"));
+    }
+
+    if crc32fast::hash(&decompressed) != crc {
+        return Err(Error::Malformed("Decompressed CRC32 mismatch"));
+    }
+
+    Ok(decompressed)
+}
+
+/// Limits for a single query, so a misbehaving server can't stall the caller
+#[derive(Debug, Clone)]
+pub struct QueryOptions {
+    /// Give up on the whole query after this long, including retries
+    pub deadline: Duration,
+    /// How long to wait for each packet
+    pub timeout: Duration,
+    /// How often to resend the query if the server doesn't answer in time
+    pub retries: usize,
+    /// Give up if the server answers with more challenges than this
+    pub max_challenges: usize,
+}
+
+impl Default for QueryOptions {
+    fn default() -> Self {
+        QueryOptions {
+            deadline: Duration::from_secs(5),
+            timeout: Duration::from_secs(2),
+            retries: 1,
+            max_challenges: 5,
+        }
+    }
+}
+
+async fn request_once(
+    sock: &UdpSocket,
+    query: &Query,
+    options: &QueryOptions,
+) -> Result<Vec<u8>, Error> {
+    let timeout = options.timeout;
 
     let mut request: Vec<u8> = Vec::with_capacity(30);
 
@@ -32,19 +202,239 @@ pub(crate) async fn send_request(sock: &UdpSocket, query: Query) -> io::Result<[
 
     request.extend_from_slice(query.get());
 
-    _ = time::timeout(timeout, sock.send(&request)).await?;
+    // The challenge goes after the info query, but replaces the placeholder `FF FF FF FF`
+    // of the others
+    let payload = match query {
+        Query::Info => request.len(),
+        Query::Player | Query::Rules => 5,
+    };
 
-    let len = time::timeout(timeout, sock.recv(&mut buf)).await??;
+    time::timeout(timeout, sock.send(&request)).await??;
+
+    let mut response = recv_response(sock, timeout).await?;
     // Challenge mechanism
-    while buf[4] == 0x41 {
-        if query == Query::Player {
-            request.truncate(5); // FF FF FF FF 'U'
+    let mut challenges = 0;
+    while response.get(4) == Some(&0x41) {
+        challenges += 1;
+        if challenges > options.max_challenges {
+            return Err(Error::ChallengeLoopExceeded);
+        }
+
+        let challenge = response
+            .get(5..9)
+            .ok_or(Error::Malformed("Truncated challenge"))?;
+
+        request.truncate(payload);
+        request.extend_from_slice(challenge);
+        time::timeout(timeout, sock.send(&request)).await??;
+        response = recv_response(sock, timeout).await?;
+    }
+
+    Ok(response)
+}
+
+pub(crate) async fn send_request(
+    sock: &UdpSocket,
+    query: Query,
+    options: &QueryOptions,
+) -> Result<Vec<u8>, Error> {
+    time::timeout(options.deadline, async {
+        let mut retries = 0;
+
+        loop {
+            match request_once(sock, &query, options).await {
+                Err(Error::Timeout) if retries < options.retries => retries += 1,
+                res => return res,
+            }
         }
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::info::get_server_info;
+    use crate::players::get_players;
+    use crate::rules::{get_rules, Rules};
+    use crate::testing::{player, Behaviour, FakeServer, FakeServerConfig};
+
+    #[tokio::test]
+    async fn challenge() {
+        let config = FakeServerConfig {
+            challenges: 3,
+            ..Default::default()
+        };
+        let server = FakeServer::start(config.clone()).await.unwrap();
+        let sock = server.connect().await.unwrap();
+        let options = QueryOptions::default();
+
+        assert_eq!(get_server_info(&sock, &options).await.unwrap(), config.info);
+        assert_eq!(get_players(&sock, 730, &options).await.unwrap(), config.players);
+        assert_eq!(get_rules(&sock, &options).await.unwrap(), config.rules);
+    }
+
+    #[tokio::test]
+    async fn challenge_loop() {
+        let config = FakeServerConfig {
+            challenges: usize::MAX,
+            ..Default::default()
+        };
+        let server = FakeServer::start(config).await.unwrap();
+        let sock = server.connect().await.unwrap();
+        let options = QueryOptions::default();
+
+        assert!(matches!(
+            get_players(&sock, 730, &options).await,
+            Err(Error::ChallengeLoopExceeded)
+        ));
+    }
+
+    fn quick() -> QueryOptions {
+        QueryOptions {
+            deadline: Duration::from_millis(500),
+            timeout: Duration::from_millis(100),
+            retries: 2,
+            max_challenges: 5,
+        }
+    }
+
+    #[tokio::test]
+    async fn silent() {
+        let config = FakeServerConfig {
+            behaviour: Behaviour::Silent,
+            ..Default::default()
+        };
+        let server = FakeServer::start(config).await.unwrap();
+        let sock = server.connect().await.unwrap();
 
-        request.extend_from_slice(&buf[5..len]);
-        _ = time::timeout(timeout, sock.send(&request)).await?;
-        _ = time::timeout(timeout, sock.recv(&mut buf)).await?;
+        let start = time::Instant::now();
+        assert!(matches!(
+            get_server_info(&sock, &quick()).await,
+            Err(Error::Timeout)
+        ));
+        assert!(start.elapsed() < Duration::from_secs(1));
     }
 
-    Ok(buf)
+    #[tokio::test]
+    async fn retries() {
+        let config = FakeServerConfig {
+            ignore: 2,
+            ..Default::default()
+        };
+        let server = FakeServer::start(config.clone()).await.unwrap();
+        let sock = server.connect().await.unwrap();
+
+        assert_eq!(get_server_info(&sock, &quick()).await.unwrap(), config.info);
+
+        server.update(|c| c.ignore = 3);
+        assert!(matches!(
+            get_server_info(&sock, &quick()).await,
+            Err(Error::Timeout)
+        ));
+    }
+
+    #[tokio::test]
+    async fn deadline() {
+        let config = FakeServerConfig {
+            delay: Duration::from_millis(80),
+            challenges: 10,
+            ..Default::default()
+        };
+        let server = FakeServer::start(config).await.unwrap();
+        let sock = server.connect().await.unwrap();
+
+        let options = QueryOptions {
+            max_challenges: 20,
+            ..quick()
+        };
+        assert!(matches!(
+            get_server_info(&sock, &options).await,
+            Err(Error::Timeout)
+        ));
+    }
+
+    fn crowded() -> FakeServerConfig {
+        let players = (0..64)
+            .map(|i| player(i, &format!("a rather long player name #{i}"), i as i32, 1.0))
+            .collect();
+
+        let rules = (0..100)
+            .map(|i| (format!("sv_dz_rule_{i}").into(), i.to_string().into()))
+            .collect();
+
+        FakeServerConfig {
+            players: crate::players::Players(players),
+            rules: Rules(rules),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn split() {
+        let config = FakeServerConfig {
+            split: Some(500),
+            reverse: true,
+            ..crowded()
+        };
+        let server = FakeServer::start(config.clone()).await.unwrap();
+        let sock = server.connect().await.unwrap();
+        let options = QueryOptions::default();
+
+        assert_eq!(get_players(&sock, 730, &options).await.unwrap(), config.players);
+        assert_eq!(get_rules(&sock, &options).await.unwrap(), config.rules);
+    }
+
+    #[tokio::test]
+    async fn compressed_split() {
+        let config = FakeServerConfig {
+            split: Some(100),
+            compress: true,
+            reverse: true,
+            ..crowded()
+        };
+        let server = FakeServer::start(config.clone()).await.unwrap();
+        let sock = server.connect().await.unwrap();
+        let options = QueryOptions::default();
+
+        assert_eq!(get_players(&sock, 730, &options).await.unwrap(), config.players);
+        assert_eq!(get_rules(&sock, &options).await.unwrap(), config.rules);
+    }
+
+    #[tokio::test]
+    async fn oversized_compression_header() {
+        let config = FakeServerConfig {
+            compress: true,
+            decompressed_size: Some(u32::MAX),
+            ..Default::default()
+        };
+        let server = FakeServer::start(config).await.unwrap();
+        let sock = server.connect().await.unwrap();
+        let options = QueryOptions::default();
+
+        assert!(matches!(
+            get_players(&sock, 730, &options).await,
+            Err(Error::Malformed("Decompressed size too large"))
+        ));
+    }
+
+    #[tokio::test]
+    async fn malformed() {
+        let config = FakeServerConfig {
+            behaviour: Behaviour::Garbage(vec![0xFF, 0xFF, 0xFF, 0xFF, b'I', 17, b'm']),
+            ..Default::default()
+        };
+        let server = FakeServer::start(config).await.unwrap();
+        let sock = server.connect().await.unwrap();
+        let options = QueryOptions::default();
+
+        assert!(matches!(get_server_info(&sock, &options).await, Err(Error::Truncated)));
+        assert!(matches!(get_players(&sock, 730, &options).await, Err(Error::BadHeader(b'I'))));
+
+        server.update(|c| c.behaviour = Behaviour::Garbage(vec![1, 2, 3, 4, 5]));
+        assert!(matches!(
+            get_players(&sock, 730, &options).await,
+            Err(Error::Malformed(_))
+        ));
+    }
 }
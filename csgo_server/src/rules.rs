@@ -0,0 +1,68 @@
+use crate::reader::Reader;
+use crate::writer::Writer;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use tokio::net::UdpSocket;
+
+use crate::{
+    request::{send_request, Query, QueryOptions},
+    Error,
+};
+
+/// Server cvars as returned by `A2S_RULES`, keyed by cvar name
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Rules(pub BTreeMap<Box<str>, Box<str>>);
+
+impl TryFrom<&[u8]> for Rules {
+    type Error = Error;
+
+    fn try_from(data: &[u8]) -> Result<Self, Error> {
+        let mut data = Reader::new(data);
+        data.header(b'E')?;
+
+        let num_rules = data.ushort()?;
+
+        let mut rules = BTreeMap::new();
+
+        for _ in 0..num_rules {
+            let name = data.string()?;
+            let value = data.string()?;
+
+            rules.insert(Box::from(name), Box::from(value));
+        }
+
+        Ok(Rules(rules))
+    }
+}
+
+impl Rules {
+    /// Encode as an `A2S_RULES` response, the inverse of `Rules::try_from`
+    #[must_use]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.header(b'E').ushort(self.0.len() as u16);
+
+        for (name, value) in &self.0 {
+            w.string(name.as_bytes()).string(value.as_bytes());
+        }
+
+        w.into_inner()
+    }
+
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(|v| &**v)
+    }
+
+    /// Interpret a cvar as a boolean, the way the engine does ("0" is false, anything else is true)
+    #[must_use]
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        self.get(name).map(|v| v.trim() != "0")
+    }
+}
+
+pub async fn get_rules(sock: &UdpSocket, options: &QueryOptions) -> Result<Rules, Error> {
+    let data = send_request(sock, Query::Rules, options).await?;
+
+    Rules::try_from(data.as_slice())
+}
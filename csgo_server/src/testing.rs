@@ -0,0 +1,441 @@
+//! Local stand-in for a Source dedicated server, so queries can be tested offline.
+//! Enable the `testing` feature to use this outside of this crate.
+
+use crate::info::{ServerEnvironment, ServerInfo, ServerType};
+use crate::players::{Player, Players};
+use crate::request::Query;
+use crate::rules::Rules;
+use crate::writer::Writer;
+use bzip2::Compression;
+use bzip2::write::BzEncoder;
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use tokio::time;
+use tokio::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Behaviour {
+    /// Answer queries normally
+    Normal,
+    /// Never answer anything
+    Silent,
+    /// Answer every query with these bytes, after any challenges
+    Garbage(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+pub struct FakeServerConfig {
+    pub info: ServerInfo,
+    pub players: Players,
+    pub rules: Rules,
+    /// Never answer `A2S_RULES`, like CS:GO without `host_rules_show 1`
+    pub hide_rules: bool,
+    /// Number of challenges sent before each real answer
+    pub challenges: usize,
+    /// Wait this long before answering
+    pub delay: Duration,
+    /// Ignore this many queries before answering, counted again after every answer
+    pub ignore: usize,
+    /// Split answers into packets carrying at most this many bytes
+    pub split: Option<usize>,
+    /// bzip2 compress split answers
+    pub compress: bool,
+    /// Claim this decompressed size in the compression header instead of the real one
+    pub decompressed_size: Option<u32>,
+    /// Send split packets last to first
+    pub reverse: bool,
+    pub behaviour: Behaviour,
+}
+
+impl Default for FakeServerConfig {
+    fn default() -> Self {
+        FakeServerConfig {
+            info: ServerInfo {
+                protocol: 17,
+                name: "meow".into(),
+                raw_name: Box::from(&b"meow"[..]),
+                map: "dz_sirocco".into(),
+                folder: "csgo".into(),
+                game: "Counter-Strike: Global Offensive".into(),
+                id: 730,
+                players: 2,
+                max_players: 18,
+                bots: 0,
+                server_type: ServerType::Dedicated,
+                server_environment: ServerEnvironment::Linux,
+                public: true,
+                vac: true,
+                the_ship: None,
+                version: "1.38.8.1".into(),
+                edf: 0x81,
+                port: Some(27015),
+                steam_id: None,
+                source_tv: None,
+                keywords: None,
+                game_id: Some(730),
+                goldsrc: None,
+            },
+            players: Players(vec![
+                player(0, "thunder", 3, 600.0),
+                player(1, "cat", 0, 60.0),
+            ]),
+            rules: Rules::default(),
+            hide_rules: false,
+            challenges: 1,
+            delay: Duration::ZERO,
+            ignore: 0,
+            split: None,
+            compress: false,
+            decompressed_size: None,
+            reverse: false,
+            behaviour: Behaviour::Normal,
+        }
+    }
+}
+
+#[must_use]
+pub fn player(index: u8, name: &str, score: i32, duration: f32) -> Player {
+    Player {
+        index,
+        name: name.into(),
+        raw_name: Box::from(name.as_bytes()),
+        score,
+        duration,
+        the_ship: None,
+    }
+}
+
+pub struct FakeServer {
+    pub addr: SocketAddr,
+    config: Arc<Mutex<FakeServerConfig>>,
+    /// Queries received by their header byte, answered or not
+    received: Arc<Mutex<HashMap<u8, usize>>>,
+    handle: JoinHandle<()>,
+}
+
+impl FakeServer {
+    /// Bind to a random local port and start answering queries
+    pub async fn start(config: FakeServerConfig) -> io::Result<Self> {
+        let sock = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr = sock.local_addr()?;
+
+        let config = Arc::new(Mutex::new(config));
+        let received = Arc::new(Mutex::new(HashMap::new()));
+        let handle = tokio::spawn(serve(sock, config.clone(), received.clone()));
+
+        Ok(FakeServer {
+            addr,
+            config,
+            received,
+            handle,
+        })
+    }
+
+    /// Change how the server answers from the next query on
+    pub fn update(&self, f: impl FnOnce(&mut FakeServerConfig)) {
+        f(&mut self.config.lock().unwrap());
+    }
+
+    /// How many queries with this header byte were received, challenges included
+    #[must_use]
+    pub fn received(&self, header: u8) -> usize {
+        self.received
+            .lock()
+            .unwrap()
+            .get(&header)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// A socket connected to this server, like the ones the bot uses
+    pub async fn connect(&self) -> io::Result<UdpSocket> {
+        let sock = UdpSocket::bind("127.0.0.1:0").await?;
+        sock.connect(self.addr).await?;
+
+        Ok(sock)
+    }
+}
+
+impl Drop for FakeServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+async fn serve(
+    sock: UdpSocket,
+    config: Arc<Mutex<FakeServerConfig>>,
+    received: Arc<Mutex<HashMap<u8, usize>>>,
+) {
+    let mut buf = [0; 1400];
+    // queries ignored and challenges sent to each client since its last real answer
+    let mut ignored: HashMap<SocketAddr, usize> = HashMap::new();
+    // with the challenge sent last, which the next query has to carry
+    let mut challenged: HashMap<SocketAddr, (usize, i32)> = HashMap::new();
+    let mut challenge: i32 = 0x1234_5678;
+
+    while let Ok((len, peer)) = sock.recv_from(&mut buf).await {
+        let config = config.lock().unwrap().clone();
+        let request = &buf[..len];
+
+        if let Some(&header) = request.get(4) {
+            *received.lock().unwrap().entry(header).or_default() += 1;
+        }
+
+        let answer = match request.get(4) {
+            Some(b'T') => config.info.encode(),
+            Some(b'U') => config.players.encode(),
+            Some(b'V') if !config.hide_rules => config.rules.encode(),
+            _ => continue,
+        };
+
+        if config.behaviour == Behaviour::Silent {
+            continue;
+        }
+
+        let count = ignored.entry(peer).or_default();
+        if *count < config.ignore {
+            *count += 1;
+            continue;
+        }
+
+        time::sleep(config.delay).await;
+
+        let (count, sent) = challenged.entry(peer).or_default();
+        // a stale or mangled challenge starts the handshake over
+        if *count > 0 && !answers(request, *sent) {
+            *count = 0;
+        }
+
+        if *count < config.challenges {
+            *count += 1;
+            challenge = challenge.wrapping_add(1);
+            *sent = challenge;
+
+            let mut w = Writer::new();
+            w.header(b'A').long(challenge);
+            _ = sock.send_to(&w.into_inner(), peer).await;
+
+            continue;
+        }
+        *count = 0;
+        ignored.insert(peer, 0);
+
+        let answer = match &config.behaviour {
+            Behaviour::Garbage(garbage) => garbage.clone(),
+            _ => answer,
+        };
+
+        for packet in packets(&config, challenge, answer) {
+            _ = sock.send_to(&packet, peer).await;
+        }
+    }
+}
+
+/// Whether `request` is a query carrying `challenge` and nothing else
+fn answers(request: &[u8], challenge: i32) -> bool {
+    let payload = match request.get(4) {
+        Some(b'T') => 4 + Query::Info.get().len(),
+        _ => 5,
+    };
+
+    request.len() == payload + 4 && request[payload..] == challenge.to_le_bytes()
+}
+
+/// Split `data` into packets the way the Source engine does
+fn packets(config: &FakeServerConfig, id: i32, data: Vec<u8>) -> Vec<Vec<u8>> {
+    let split = match (config.split, config.compress) {
+        (None, false) => return vec![data],
+        (Some(split), _) => split,
+        (None, true) => 1248,
+    };
+
+    let mut id = id as u32 & 0x7FFF_FFFF;
+    let mut compression = None;
+
+    let payload = if config.compress {
+        id |= 0x8000_0000;
+        let size = config.decompressed_size.unwrap_or(data.len() as u32);
+        compression = Some((size as i32, crc32fast::hash(&data) as i32));
+
+        let mut encoder = BzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&data).unwrap();
+        encoder.finish().unwrap()
+    } else {
+        data
+    };
+
+    let chunks: Vec<&[u8]> = payload.chunks(split.max(1)).collect();
+    let total = chunks.len() as u8;
+
+    let mut packets: Vec<Vec<u8>> = chunks
+        .into_iter()
+        .enumerate()
+        .map(|(number, chunk)| {
+            let mut w = Writer::new();
+            w.long(-2)
+                .long(id as i32)
+                .byte(total)
+                .byte(number as u8)
+                .short(split as i16);
+
+            if let (0, Some((size, crc))) = (number, compression) {
+                w.long(size).long(crc);
+            }
+
+            w.bytes(chunk);
+            w.into_inner()
+        })
+        .collect();
+
+    if config.reverse {
+        packets.reverse();
+    }
+
+    packets
+}
+
+/// Local stand-in for a server's RCON port
+pub struct FakeRcon {
+    pub addr: SocketAddr,
+    handle: JoinHandle<()>,
+}
+
+impl FakeRcon {
+    /// Answers every command with `output(command)`, split into packets of at most `split` bytes
+    pub async fn start(
+        password: &str,
+        split: usize,
+        output: fn(&str) -> String,
+    ) -> io::Result<Self> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let password = password.as_bytes().to_vec();
+
+        let handle = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve_rcon(stream, password.clone(), split.max(1), output));
+            }
+        });
+
+        Ok(FakeRcon { addr, handle })
+    }
+}
+
+impl Drop for FakeRcon {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+async fn serve_rcon(
+    mut stream: tokio::net::TcpStream,
+    password: Vec<u8>,
+    split: usize,
+    output: fn(&str) -> String,
+) {
+    use crate::rcon::*;
+    use tokio::io::AsyncWriteExt;
+
+    while let Ok(packet) = Packet::read(&mut stream).await {
+        let mut answer: Vec<Packet> = Vec::new();
+        let response = |id, body: &[u8]| Packet {
+            id,
+            kind: SERVERDATA_RESPONSE_VALUE,
+            body: body.to_vec(),
+        };
+
+        match packet.kind {
+            SERVERDATA_AUTH => {
+                answer.push(response(packet.id, &[]));
+                answer.push(Packet {
+                    id: if packet.body == password {
+                        packet.id
+                    } else {
+                        -1
+                    },
+                    kind: SERVERDATA_AUTH_RESPONSE,
+                    body: vec![],
+                });
+            }
+            SERVERDATA_EXECCOMMAND => {
+                let command = String::from_utf8_lossy(&packet.body);
+                let out = output(&command);
+
+                for chunk in out.as_bytes().chunks(split) {
+                    answer.push(response(packet.id, chunk));
+                }
+            }
+            // Mirror empty packets, followed by the odd packet real servers send after it
+            _ => {
+                answer.push(response(packet.id, &[]));
+                answer.push(response(packet.id, &[0, 1, 0, 0]));
+            }
+        }
+
+        for p in answer {
+            if stream.write_all(&p.encode()).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Local stand-in for the Steam master server, answering every query with `servers`,
+/// `page` addresses at a time
+pub struct FakeMaster {
+    pub addr: SocketAddr,
+    handle: JoinHandle<()>,
+}
+
+impl FakeMaster {
+    pub async fn start(servers: Vec<std::net::SocketAddrV4>, page: usize) -> io::Result<Self> {
+        use crate::master::encode_response;
+        use std::net::{Ipv4Addr, SocketAddrV4};
+
+        let sock = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr = sock.local_addr()?;
+
+        let handle = tokio::spawn(async move {
+            let mut buf = [0; 1400];
+
+            while let Ok((len, peer)) = sock.recv_from(&mut buf).await {
+                let mut data = crate::reader::Reader::new(&buf[..len]);
+                let seed = match (data.byte(), data.byte(), data.string()) {
+                    (Ok(0x31), Ok(_), Ok(seed)) => seed.parse::<SocketAddrV4>(),
+                    _ => continue,
+                };
+                let Ok(seed) = seed else { continue };
+
+                let start = match seed {
+                    s if s.ip().is_unspecified() => 0,
+                    s => servers
+                        .iter()
+                        .position(|a| *a == s)
+                        .map_or(servers.len(), |i| i + 1),
+                };
+
+                let mut answer: Vec<SocketAddrV4> =
+                    servers.iter().skip(start).take(page).copied().collect();
+                if start + page >= servers.len() {
+                    answer.push(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0));
+                }
+
+                _ = sock.send_to(&encode_response(&answer), peer).await;
+            }
+        });
+
+        Ok(FakeMaster { addr, handle })
+    }
+}
+
+impl Drop for FakeMaster {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
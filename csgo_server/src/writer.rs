@@ -0,0 +1,56 @@
+/// Builds a response packet, the counterpart of `Reader`
+#[derive(Debug, Clone, Default)]
+pub struct Writer {
+    data: Vec<u8>,
+}
+
+impl Writer {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        self.data.extend_from_slice(bytes);
+        self
+    }
+
+    /// `FF FF FF FF` packet header followed by the response type
+    pub fn header(&mut self, kind: u8) -> &mut Self {
+        self.bytes(&[0xFF, 0xFF, 0xFF, 0xFF, kind])
+    }
+
+    pub fn byte(&mut self, v: u8) -> &mut Self {
+        self.bytes(&[v])
+    }
+
+    pub fn short(&mut self, v: i16) -> &mut Self {
+        self.bytes(&v.to_le_bytes())
+    }
+
+    pub fn ushort(&mut self, v: u16) -> &mut Self {
+        self.bytes(&v.to_le_bytes())
+    }
+
+    pub fn long(&mut self, v: i32) -> &mut Self {
+        self.bytes(&v.to_le_bytes())
+    }
+
+    pub fn float(&mut self, v: f32) -> &mut Self {
+        self.bytes(&v.to_le_bytes())
+    }
+
+    pub fn long_long(&mut self, v: u64) -> &mut Self {
+        self.bytes(&v.to_le_bytes())
+    }
+
+    /// Null terminated string, `s` must not contain a null byte
+    pub fn string(&mut self, s: &[u8]) -> &mut Self {
+        self.bytes(s).byte(0)
+    }
+}
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE server_settings ADD COLUMN rcon_password TEXT;
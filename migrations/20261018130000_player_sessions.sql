@@ -0,0 +1,14 @@
+-- Add migration script here
+CREATE TABLE player_sessions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    server_name TEXT NOT NULL,
+    player_name TEXT NOT NULL,
+    -- unix timestamps
+    join_time INTEGER NOT NULL,
+    leave_time INTEGER,
+    last_seen INTEGER NOT NULL,
+    peak_score INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX player_sessions_server_join ON player_sessions (server_name, join_time);
+CREATE INDEX player_sessions_player ON player_sessions (player_name);
@@ -0,0 +1,12 @@
+-- Add migration script here
+CREATE TABLE population_samples (
+    server_name TEXT NOT NULL,
+    -- unix timestamp
+    time INTEGER NOT NULL,
+    players INTEGER NOT NULL,
+    bots INTEGER NOT NULL,
+    max_players INTEGER NOT NULL,
+    map TEXT NOT NULL
+);
+
+CREATE INDEX population_samples_server_time ON population_samples (server_name, time);
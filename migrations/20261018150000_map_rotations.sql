@@ -0,0 +1,15 @@
+-- Add migration script here
+CREATE TABLE map_rotations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    server_name TEXT NOT NULL,
+    map TEXT NOT NULL,
+    -- unix timestamps
+    start_time INTEGER NOT NULL,
+    end_time INTEGER,
+    last_seen INTEGER NOT NULL,
+    peak_players INTEGER NOT NULL DEFAULT 0,
+    -- players integrated over time, divide by the duration for the average
+    player_seconds INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX map_rotations_server_start ON map_rotations (server_name, start_time);
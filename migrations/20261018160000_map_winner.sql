@@ -0,0 +1,3 @@
+-- Add migration script here
+-- top scorer when the map changed, NULL if the server went down or nobody scored
+ALTER TABLE map_rotations ADD COLUMN winner TEXT;
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- seconds between queries of the server
+ALTER TABLE server_settings ADD COLUMN poll_interval INTEGER NOT NULL DEFAULT 5;
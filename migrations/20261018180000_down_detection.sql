@@ -0,0 +1,7 @@
+-- Add migration script here
+-- consecutive failed queries before a server counts as down
+ALTER TABLE server_settings ADD COLUMN down_failures INTEGER NOT NULL DEFAULT 3;
+-- consecutive successful queries before a down server counts as up
+ALTER TABLE server_settings ADD COLUMN down_recoveries INTEGER NOT NULL DEFAULT 2;
+-- seconds a server has to be down before anyone is notified
+ALTER TABLE server_settings ADD COLUMN down_grace INTEGER NOT NULL DEFAULT 120;
@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE server_alerts (
+    server_name TEXT PRIMARY KEY NOT NULL,
+    channel_id TEXT NOT NULL CHECK (channel_id GLOB '[0-9]*'),
+    role_id TEXT CHECK (role_id GLOB '[0-9]*'),
+    -- space separated user ids
+    user_ids TEXT NOT NULL DEFAULT '',
+    enabled BOOLEAN NOT NULL DEFAULT TRUE
+);
+
+-- the down detector used to be hardcoded to this
+INSERT INTO server_alerts (server_name, channel_id, role_id)
+       SELECT name, '1224415507495649330', '1223090099164549200' FROM server_settings WHERE name = 'meow';
@@ -0,0 +1,11 @@
+-- Add migration script here
+CREATE TABLE server_webhooks (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    server_name TEXT NOT NULL,
+    kind TEXT NOT NULL CHECK (kind IN ('discord', 'json', 'ntfy')),
+    url TEXT NOT NULL,
+    -- space separated event names
+    events TEXT NOT NULL DEFAULT 'down recovered map_change full'
+);
+
+CREATE INDEX server_webhooks_server ON server_webhooks (server_name);
@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE player_subscriptions (
+    user_id TEXT NOT NULL CHECK (user_id GLOB '[0-9]*'),
+    server_name TEXT NOT NULL,
+    min_players INTEGER NOT NULL,
+    -- mention the user here instead of sending a DM
+    channel_id TEXT CHECK (channel_id GLOB '[0-9]*'),
+    -- seconds
+    cooldown INTEGER NOT NULL DEFAULT 3600,
+    -- unix timestamp
+    last_notified INTEGER,
+    PRIMARY KEY (user_id, server_name)
+);
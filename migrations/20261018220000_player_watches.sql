@@ -0,0 +1,12 @@
+-- Add migration script here
+CREATE TABLE player_watches (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id TEXT NOT NULL CHECK (user_id GLOB '[0-9]*'),
+    pattern TEXT NOT NULL,
+    -- glob otherwise
+    regex BOOLEAN NOT NULL DEFAULT FALSE,
+    -- every server if NULL
+    server_name TEXT
+);
+
+CREATE INDEX player_watches_user ON player_watches (user_id);
@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TABLE gamestate_snapshots (
+    token TEXT PRIMARY KEY NOT NULL REFERENCES gamestate_integration (token) ON DELETE CASCADE,
+    -- json of the last payload, without the token
+    state TEXT NOT NULL,
+    -- unix timestamp
+    received INTEGER NOT NULL
+);
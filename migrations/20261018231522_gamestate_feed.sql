@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE gamestate_integration ADD COLUMN feed_channel_id TEXT CHECK (feed_channel_id GLOB '[0-9]*');
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- host:port of the master server /discover asks, Valve's if NULL
+ALTER TABLE settings ADD COLUMN master_server_address TEXT;
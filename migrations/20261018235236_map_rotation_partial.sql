@@ -0,0 +1,6 @@
+-- Add migration script here
+-- the start or the end of the rotation wasn't seen because the bot or the server was down,
+-- its length says nothing about how long a match lasts
+ALTER TABLE map_rotations ADD COLUMN partial BOOLEAN NOT NULL DEFAULT FALSE;
+-- the server was still on the map of the previous, partial rotation, it's not another play of it
+ALTER TABLE map_rotations ADD COLUMN continued BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,5 @@
+-- Add migration script here
+-- Servers the alert channel was told are down, so a restart still says when they're back up
+CREATE TABLE down_notifications (
+    server_name TEXT PRIMARY KEY NOT NULL
+);
@@ -0,0 +1,398 @@
+use crate::db::DbConnection;
+use crate::privilege_check;
+use crate::servers::Servers;
+use crate::{Context, Error};
+use once_cell::sync::Lazy;
+use poise::serenity_prelude as serenity;
+use poise::serenity_prelude::prelude::TypeMapKey;
+use poise::CreateReply;
+use regex::Regex;
+use serenity::{ChannelId, RoleId, UserId};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Where the down detector reports a server
+#[derive(Debug, Clone)]
+pub struct Alert {
+    pub channel: ChannelId,
+    pub role: Option<RoleId>,
+    pub users: Vec<UserId>,
+    pub enabled: bool,
+}
+
+impl Alert {
+    pub fn mentions(&self) -> String {
+        self.role
+            .iter()
+            .map(|r| format!("<@&{r}>"))
+            .chain(self.users.iter().map(|u| format!("<@{u}>")))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+pub struct Alerts;
+// server name, alert
+pub type AlertsValue = HashMap<String, Alert>;
+impl TypeMapKey for Alerts {
+    type Value = AlertsValue;
+}
+
+static USER_ID: Lazy<Regex> = Lazy::new(|| Regex::new(r"\d{15,20}").unwrap());
+
+fn alerts_help() -> String {
+    "Configure where the bot says a server went down or came back up.
+Requires admin privileges."
+        .into()
+}
+
+#[poise::command(
+    slash_command,
+    subcommands("set", "clear", "list"),
+    check = "privilege_check",
+    category = "Alerts",
+    help_text_fn = "alerts_help"
+)]
+pub async fn alerts(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Options given to `/alerts set`, `None` keeps what the server already has
+struct Changes {
+    channel: Option<ChannelId>,
+    role: Option<RoleId>,
+    clear_role: bool,
+    users: Option<String>,
+    enabled: Option<bool>,
+}
+
+impl Changes {
+    fn apply(self, existing: Option<&Alert>, here: ChannelId) -> Result<Alert, Error> {
+        if self.clear_role && self.role.is_some() {
+            return Err("Can't set a role and clear it at once".into());
+        }
+
+        Ok(Alert {
+            channel: self.channel.or(existing.map(|a| a.channel)).unwrap_or(here),
+            role: match self.role {
+                _ if self.clear_role => None,
+                Some(role) => Some(role),
+                None => existing.and_then(|a| a.role),
+            },
+            users: match self.users {
+                Some(users) => USER_ID
+                    .find_iter(&users)
+                    .filter_map(|id| id.as_str().parse::<u64>().ok())
+                    .map(UserId::new)
+                    .collect(),
+                None => existing.map(|a| a.users.clone()).unwrap_or_default(),
+            },
+            enabled: self.enabled.or(existing.map(|a| a.enabled)).unwrap_or(true),
+        })
+    }
+}
+
+/// Set the alert channel and who gets pinged, omitted options are kept
+#[poise::command(slash_command, check = "privilege_check", category = "Alerts")]
+async fn set(
+    ctx: Context<'_>,
+    #[description = "Server identifier"] name: String,
+    #[description = "Defaults to this channel"] channel: Option<serenity::GuildChannel>,
+    #[description = "Role to mention"] role: Option<serenity::Role>,
+    #[description = "Stop mentioning the role"] clear_role: Option<bool>,
+    #[description = "Users to mention"] users: Option<String>,
+    #[description = "Send alerts at all"] enabled: Option<bool>,
+) -> Result<(), Error> {
+    let mut data = ctx.serenity_context().data.write().await;
+
+    if !data
+        .get::<Servers>()
+        .ok_or("DataError: Unable to get servers")?
+        .contains_key(&name)
+    {
+        return Err(format!("Server {name} doesn't exist").into());
+    }
+
+    let alerts = data
+        .get_mut::<Alerts>()
+        .ok_or("DataError: Unable to get alerts")?;
+    let alert = Changes {
+        channel: channel.map(|c| c.id),
+        role: role.map(|r| r.id),
+        clear_role: clear_role.unwrap_or(false),
+        users,
+        enabled,
+    }
+    .apply(alerts.get(&name), ctx.channel_id())?;
+
+    alerts.insert(name.clone(), alert.clone());
+
+    let conn = data
+        .get_mut::<DbConnection>()
+        .ok_or("DataError: Unable to get database connection")?;
+    db::write_alert(conn, &name, &alert).await?;
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!("Alerts for `{}`: {}", name, describe(&alert)))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Stop sending alerts for a server
+#[poise::command(slash_command, check = "privilege_check", category = "Alerts")]
+async fn clear(
+    ctx: Context<'_>,
+    #[description = "Server identifier"] name: String,
+) -> Result<(), Error> {
+    let mut data = ctx.serenity_context().data.write().await;
+
+    let alerts = data
+        .get_mut::<Alerts>()
+        .ok_or("DataError: Unable to get alerts")?;
+    alerts.remove(&name);
+
+    let conn = data
+        .get_mut::<DbConnection>()
+        .ok_or("DataError: Unable to get database connection")?;
+    db::remove_alert(conn, &name).await?;
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!("Cleared alerts for `{}`", name))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// List where alerts for every server go
+#[poise::command(slash_command, check = "privilege_check", category = "Alerts")]
+async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    let data = ctx.serenity_context().data.read().await;
+
+    let alerts = data
+        .get::<Alerts>()
+        .ok_or("DataError: Unable to get alerts")?;
+
+    let list = alerts
+        .iter()
+        .fold(String::new(), |mut output, (name, alert)| {
+            _ = writeln!(output, "`{}` - {}", name, describe(alert));
+            output
+        });
+
+    ctx.send(
+        CreateReply::default()
+            .content(if list.is_empty() {
+                "No alerts configured".to_string()
+            } else {
+                list
+            })
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+fn describe(alert: &Alert) -> String {
+    let mentions = alert.mentions();
+
+    format!(
+        "<#{}>{}{}",
+        alert.channel,
+        if mentions.is_empty() {
+            ", nobody is mentioned".to_string()
+        } else {
+            format!(", mentions {mentions}")
+        },
+        if alert.enabled { "" } else { " (disabled)" }
+    )
+}
+
+pub mod db {
+    use super::{Alert, AlertsValue};
+    use crate::Error;
+    use poise::serenity_prelude::{ChannelId, RoleId, UserId};
+    use sqlx::SqliteConnection;
+
+    pub async fn read_alerts(conn: &mut SqliteConnection) -> Result<AlertsValue, Error> {
+        struct Fetch {
+            server_name: String,
+            channel_id: String,
+            role_id: Option<String>,
+            user_ids: String,
+            enabled: bool,
+        }
+
+        let alerts = sqlx::query_as!(
+            Fetch,
+            "SELECT server_name, channel_id, role_id, user_ids, enabled FROM server_alerts"
+        )
+        .fetch_all(conn)
+        .await?;
+
+        Ok(alerts
+            .into_iter()
+            .filter_map(|v| {
+                Some((
+                    v.server_name,
+                    Alert {
+                        channel: ChannelId::new(v.channel_id.parse().ok()?),
+                        role: v.role_id.and_then(|r| r.parse().ok()).map(RoleId::new),
+                        users: v
+                            .user_ids
+                            .split_whitespace()
+                            .filter_map(|u| u.parse().ok())
+                            .map(UserId::new)
+                            .collect(),
+                        enabled: v.enabled,
+                    },
+                ))
+            })
+            .collect())
+    }
+
+    pub async fn write_alert(
+        conn: &mut SqliteConnection,
+        name: &str,
+        alert: &Alert,
+    ) -> Result<(), Error> {
+        let channel = alert.channel.to_string();
+        let role = alert.role.map(|r| r.to_string());
+        let users = alert
+            .users
+            .iter()
+            .map(|u| u.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        sqlx::query!(
+            "INSERT INTO server_alerts (server_name, channel_id, role_id, user_ids, enabled) VALUES (?, ?, ?, ?, ?)
+ON CONFLICT(server_name) DO UPDATE
+SET channel_id = excluded.channel_id,
+    role_id = excluded.role_id,
+    user_ids = excluded.user_ids,
+    enabled = excluded.enabled",
+            name,
+            channel,
+            role,
+            users,
+            alert.enabled
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_alert(conn: &mut SqliteConnection, name: &str) -> Result<(), Error> {
+        sqlx::query!("DELETE FROM server_alerts WHERE server_name = ?", name)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn changes() -> Changes {
+        Changes {
+            channel: None,
+            role: None,
+            clear_role: false,
+            users: None,
+            enabled: None,
+        }
+    }
+
+    fn existing() -> Alert {
+        Alert {
+            channel: ChannelId::new(1),
+            role: Some(RoleId::new(2)),
+            users: vec![UserId::new(3)],
+            enabled: false,
+        }
+    }
+
+    #[test]
+    fn new_alert() {
+        let alert = changes().apply(None, ChannelId::new(9)).unwrap();
+
+        assert_eq!(alert.channel, ChannelId::new(9));
+        assert_eq!(alert.role, None);
+        assert!(alert.users.is_empty());
+        assert!(alert.enabled);
+    }
+
+    #[test]
+    fn keeps_existing() {
+        let alert = changes()
+            .apply(Some(&existing()), ChannelId::new(9))
+            .unwrap();
+
+        assert_eq!(alert.channel, ChannelId::new(1));
+        assert_eq!(alert.role, Some(RoleId::new(2)));
+        assert_eq!(alert.users, vec![UserId::new(3)]);
+        assert!(!alert.enabled);
+    }
+
+    #[test]
+    fn replaces_existing() {
+        let alert = Changes {
+            channel: Some(ChannelId::new(4)),
+            role: Some(RoleId::new(5)),
+            users: Some("<@111111111111111111> and 222222222222222222".into()),
+            enabled: Some(true),
+            ..changes()
+        }
+        .apply(Some(&existing()), ChannelId::new(9))
+        .unwrap();
+
+        assert_eq!(alert.channel, ChannelId::new(4));
+        assert_eq!(alert.role, Some(RoleId::new(5)));
+        assert_eq!(
+            alert.users,
+            vec![
+                UserId::new(111111111111111111),
+                UserId::new(222222222222222222)
+            ]
+        );
+        assert!(alert.enabled);
+
+        // an empty list removes everyone
+        let alert = Changes {
+            users: Some(String::new()),
+            ..changes()
+        }
+        .apply(Some(&existing()), ChannelId::new(9))
+        .unwrap();
+        assert!(alert.users.is_empty());
+    }
+
+    #[test]
+    fn clears_role() {
+        let clear = || Changes {
+            clear_role: true,
+            ..changes()
+        };
+
+        let alert = clear().apply(Some(&existing()), ChannelId::new(9)).unwrap();
+        assert_eq!(alert.role, None);
+        assert_eq!(alert.users, vec![UserId::new(3)]);
+
+        let both = Changes {
+            role: Some(RoleId::new(5)),
+            ..clear()
+        };
+        assert!(both.apply(Some(&existing()), ChannelId::new(9)).is_err());
+    }
+}
@@ -0,0 +1,355 @@
+use crate::db::DbConnection;
+use crate::poller::{start_poller, Pollers};
+use crate::privilege_check;
+use crate::servers::db::write_server;
+use crate::servers::{Server, Servers, ServersValue};
+use crate::settings::Settings;
+use crate::{Context, Error};
+use ::serenity::all::{
+    ComponentInteractionCollector, CreateActionRow, CreateButton, CreateInteractionResponse,
+    CreateInteractionResponseMessage,
+};
+use csgo_server::info::{get_server_info, ServerInfo};
+use csgo_server::master::{MasterServer, Region};
+use csgo_server::request::QueryOptions;
+use poise::CreateReply;
+use std::cmp::Reverse;
+use std::fmt::Write;
+use std::net::SocketAddrV4;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::task::JoinSet;
+
+const DEFAULT_FILTER: &str = r"\gamedir\csgo\map\dz_*";
+// only this many servers from the master server are queried
+const MAX_QUERIED: usize = 50;
+// 2 rows of 5 buttons
+const MAX_RESULTS: usize = 10;
+
+/// A filter starting with `\` goes to the master server as is,
+/// anything else is a map pattern like `dz_*`
+fn build_filter(filter: Option<&str>) -> String {
+    match filter.map(str::trim) {
+        None | Some("") => DEFAULT_FILTER.into(),
+        Some(f) if f.starts_with('\\') => f.into(),
+        Some(map) => format!(r"\gamedir\csgo\map\{map}"),
+    }
+}
+
+async fn query(addr: SocketAddrV4, options: QueryOptions) -> Option<(SocketAddrV4, ServerInfo)> {
+    let sock = UdpSocket::bind("0.0.0.0:0").await.ok()?;
+    sock.connect(addr).await.ok()?;
+
+    let info = get_server_info(&sock, &options).await.ok()?;
+
+    Some((addr, info))
+}
+
+/// Servers `master` lists for `filter` that answer a query, busiest first
+async fn find(
+    master: &MasterServer,
+    filter: &str,
+    options: &QueryOptions,
+) -> Result<Vec<(SocketAddrV4, ServerInfo)>, Error> {
+    let addrs = master.servers(Region::World, filter, options).await?;
+
+    let mut queries = JoinSet::new();
+    for addr in addrs.into_iter().take(MAX_QUERIED) {
+        queries.spawn(query(addr, options.clone()));
+    }
+
+    let mut found: Vec<(SocketAddrV4, ServerInfo)> =
+        queries.join_all().await.into_iter().flatten().collect();
+
+    found.sort_by_key(|(_, info)| Reverse(info.players));
+    found.truncate(MAX_RESULTS);
+
+    Ok(found)
+}
+
+/// Turn a server name into an unused server identifier
+fn identifier(name: &str, servers: &ServersValue) -> String {
+    let base = name
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+        .chars()
+        .take(24)
+        .collect::<String>();
+
+    let base = if base.is_empty() {
+        "server".into()
+    } else {
+        base
+    };
+
+    let mut ident = base.clone();
+    let mut i = 2;
+    while servers.contains_key(&ident) {
+        ident = format!("{base}-{i}");
+        i += 1;
+    }
+
+    ident
+}
+
+async fn import(ctx: Context<'_>, addr: &SocketAddrV4, info: &ServerInfo) -> Result<String, Error> {
+    let mut data = ctx.serenity_context().data.write().await;
+
+    let servers = data
+        .get::<Servers>()
+        .ok_or("DataError: Unable to get servers")?;
+
+    if let Some(server) = servers.values().find(|s| s.addr == addr.to_string()) {
+        return Ok(server.name.clone());
+    }
+
+    let name = identifier(&info.name, servers);
+    let server = Server {
+        name: name.clone(),
+        addr: addr.to_string(),
+        max_player_count: info.max_players as i64,
+        legacy: true,
+        allow_upload_required: false,
+        rcon_password: None,
+        poll_interval: 5,
+        down_failures: 3,
+        down_recoveries: 2,
+        down_grace: 120,
+    };
+
+    let conn = data
+        .get_mut::<DbConnection>()
+        .ok_or("DataError: Unable to get database connection")?;
+    write_server(&server, conn).await?;
+
+    let pollers = data
+        .get_mut::<Pollers>()
+        .ok_or("DataError: Unable to get pollers")?;
+    start_poller(pollers, &server).await?;
+
+    let servers = data
+        .get_mut::<Servers>()
+        .ok_or("DataError: Unable to get servers")?;
+    servers.insert(name.clone(), server);
+
+    Ok(name)
+}
+
+fn discover_help() -> String {
+    "List public Danger Zone servers from the Steam master server, and import them with one click.
+Requires admin privileges."
+        .into()
+}
+
+#[poise::command(
+    slash_command,
+    check = "privilege_check",
+    help_text_fn = "discover_help"
+)]
+pub async fn discover(
+    ctx: Context<'_>,
+    #[description = "Map pattern or master server filter, defaults to \\gamedir\\csgo\\map\\dz_*"]
+    filter: Option<String>,
+) -> Result<(), Error> {
+    ctx.defer_ephemeral().await?;
+
+    let master = {
+        let data = ctx.serenity_context().data.read().await;
+
+        data.get::<Settings>()
+            .ok_or("DataError: Unable to get settings")?
+            .master_server_address
+            .as_deref()
+            .map(MasterServer::new)
+            .unwrap_or_default()
+    };
+
+    let filter = build_filter(filter.as_deref());
+    let options = QueryOptions {
+        deadline: Duration::from_secs(3),
+        timeout: Duration::from_secs(1),
+        ..Default::default()
+    };
+
+    let found = find(&master, &filter, &options).await?;
+
+    if found.is_empty() {
+        ctx.send(
+            CreateReply::default()
+                .content("No servers found")
+                .ephemeral(true),
+        )
+        .await?;
+
+        return Ok(());
+    }
+
+    let list = found
+        .iter()
+        .enumerate()
+        .fold(String::new(), |mut output, (i, (addr, info))| {
+            _ = writeln!(
+                output,
+                "{}. {} - {} - {}/{} - {}",
+                i + 1,
+                info.name,
+                info.map,
+                info.players,
+                info.max_players,
+                addr
+            );
+            output
+        });
+
+    let prefix = format!("{}-import-", ctx.id());
+    let buttons = found
+        .chunks(5)
+        .enumerate()
+        .map(|(row, chunk)| {
+            CreateActionRow::Buttons(
+                (0..chunk.len())
+                    .map(|i| {
+                        let i = row * 5 + i;
+                        CreateButton::new(format!("{prefix}{i}")).label(format!("Import {}", i + 1))
+                    })
+                    .collect(),
+            )
+        })
+        .collect();
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!("```\n{}\n```", list))
+            .components(buttons)
+            .ephemeral(true),
+    )
+    .await?;
+
+    while let Some(press) = ComponentInteractionCollector::new(ctx)
+        .author_id(ctx.author().id)
+        .filter({
+            let prefix = prefix.clone();
+            move |press| press.data.custom_id.starts_with(&prefix)
+        })
+        .timeout(Duration::from_secs(300))
+        .await
+    {
+        let (addr, info) = press
+            .data
+            .custom_id
+            .strip_prefix(&prefix)
+            .and_then(|i| i.parse::<usize>().ok())
+            .and_then(|i| found.get(i))
+            .ok_or("Invalid button")?;
+
+        let name = import(ctx, addr, info).await?;
+
+        press
+            .create_response(
+                ctx.serenity_context(),
+                CreateInteractionResponse::Message(
+                    CreateInteractionResponseMessage::new()
+                        .content(format!("Imported `{}` as `{}`", info.name, name))
+                        .ephemeral(true),
+                ),
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csgo_server::testing::{FakeMaster, FakeServer, FakeServerConfig};
+    use std::net::SocketAddr;
+
+    #[test]
+    fn filter() {
+        assert_eq!(build_filter(None), DEFAULT_FILTER);
+        assert_eq!(build_filter(Some(" ")), DEFAULT_FILTER);
+        assert_eq!(build_filter(Some("dz_sir*")), r"\gamedir\csgo\map\dz_sir*");
+        assert_eq!(
+            build_filter(Some(r"\gamedir\csgo\empty\1")),
+            r"\gamedir\csgo\empty\1"
+        );
+    }
+
+    #[test]
+    fn identifiers() {
+        let mut servers = ServersValue::new();
+        assert_eq!(
+            identifier("Meowdz | Danger Zone #1", &servers),
+            "meowdz-danger-zone-1"
+        );
+        assert_eq!(identifier("★★★", &servers), "server");
+
+        servers.insert(
+            "meowdz-danger-zone-1".into(),
+            Server {
+                name: "meowdz-danger-zone-1".into(),
+                addr: "127.0.0.1:27015".into(),
+                max_player_count: 18,
+                legacy: true,
+                allow_upload_required: false,
+                rcon_password: None,
+                poll_interval: 5,
+                down_failures: 3,
+                down_recoveries: 2,
+                down_grace: 120,
+            },
+        );
+        assert_eq!(
+            identifier("Meowdz | Danger Zone #1", &servers),
+            "meowdz-danger-zone-1-2"
+        );
+    }
+
+    fn v4(addr: SocketAddr) -> SocketAddrV4 {
+        match addr {
+            SocketAddr::V4(addr) => addr,
+            SocketAddr::V6(_) => panic!("fake servers bind to 127.0.0.1"),
+        }
+    }
+
+    #[tokio::test]
+    async fn busiest_first() {
+        let quiet = FakeServer::start(FakeServerConfig::default())
+            .await
+            .unwrap();
+
+        let mut config = FakeServerConfig::default();
+        config.info.players = 12;
+        let busy = FakeServer::start(config).await.unwrap();
+
+        // nothing listens here anymore
+        let dead = UdpSocket::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let listed = [quiet.addr, dead, busy.addr].map(v4).to_vec();
+        let master = FakeMaster::start(listed, 2).await.unwrap();
+
+        let options = QueryOptions {
+            deadline: Duration::from_millis(500),
+            timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let found = find(
+            &MasterServer::new(&master.addr.to_string()),
+            DEFAULT_FILTER,
+            &options,
+        )
+        .await
+        .unwrap();
+
+        let found: Vec<SocketAddrV4> = found.into_iter().map(|(addr, _)| addr).collect();
+        assert_eq!(found, vec![v4(busy.addr), v4(quiet.addr)]);
+    }
+}
@@ -1,66 +1,412 @@
-use std::time::SystemTime;
-use std::time::UNIX_EPOCH;
+use crate::alerts::Alert;
+use crate::alerts::Alerts;
+use crate::alerts::AlertsValue;
+use crate::db::DbConnection;
+use crate::poller::start_poller;
+use crate::poller::Poller;
+use crate::poller::Pollers;
+use crate::privilege_check;
 use crate::server_info::Info;
-use crate::server_info::get_server_info;
-use crate::server_info::INFO;
-use crate::{Error, ServerSocket};
+use crate::servers::db::write_server;
+use crate::servers::Server;
+use crate::servers::Servers;
+use crate::webhooks::dispatch;
+use crate::webhooks::Event;
+use crate::webhooks::Webhooks;
+use crate::webhooks::WebhooksValue;
+use crate::{Context, Error};
 use ::serenity::all::CreateMessage;
-use tokio::time;
-use std::time::Duration;
-use std::sync::Arc;
 use poise::serenity_prelude as serenity;
+use poise::CreateReply;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+use tokio::time;
 
-// TODO unhardcode these
-const CHID: u64 = 1224415507495649330;
-const COP_CAT: u64 = 1223090099164549200;
-// TODO store channel id + role/user id per game server
+/// What the down detector remembers about the servers between checks
+#[derive(Default)]
+pub struct Detector {
+    /// Servers we've said are down, so we can say when they're back up. Stored in the database
+    /// since a restart, a resume or a new poller would otherwise forget them
+    notified: HashSet<String>,
+    /// Last map seen on each server, kept while it's down so a restart onto a new map counts
+    maps: HashMap<String, Box<str>>,
+    full: HashSet<String>,
+}
 
-// TODO think about this more
+impl Detector {
+    /// Drop what's remembered about servers that were deleted, their name may be reused
+    fn forget_deleted(&mut self, exists: impl Fn(&str) -> bool) {
+        self.notified.retain(|name| exists(name));
+        self.maps.retain(|name, _| exists(name));
+        self.full.retain(|name| exists(name));
+    }
+
+    fn observe(&mut self, name: &str, server: &Server, info: &Info) -> Result<Vec<Event>, Error> {
+        let mut events = vec![];
+
+        match info {
+            Info::ServerDown(down) => {
+                self.full.remove(name);
 
-pub async fn down_detector(ctx: &serenity::Context) -> Result<(), Error> {
+                let down_for = SystemTime::now().duration_since(down.since)?.as_secs();
 
-    let data = ctx.data.read().await;
+                if down_for >= server.down_grace as u64 && self.notified.insert(name.to_string()) {
+                    events.push(Event::Down {
+                        since: down.since.duration_since(UNIX_EPOCH)?.as_secs(),
+                    });
+                }
+            }
+            Info::ServerUp(up) => {
+                if self.notified.remove(name) {
+                    events.push(Event::Recovered);
+                }
 
-    let socks = data
-        .get::<ServerSocket>()
-        .ok_or("DataError: Unable to get sockets")?;
+                let map = &up.server_info.map;
+                match self.maps.insert(name.to_string(), map.clone()) {
+                    Some(from) if from != *map => events.push(Event::MapChange {
+                        from: from.into(),
+                        to: map.to_string(),
+                    }),
+                    _ => (),
+                }
 
-    // TODO unhardcode this shit
-    for name in ["meow".to_string()] {
-	if let Info::ServerDown(down) = get_server_info(socks, &name).await? {
-	    if SystemTime::now().duration_since(down.since)?.as_secs() > 120 && !down.ping_sent {
-		let channel = serenity::ChannelId::from(CHID);
-		channel.send_message(&ctx, CreateMessage::new().content(
-		    format!("<@&{}> Server `{name}` went down <t:{}:R>!",
-			    COP_CAT,
-			    down.since.duration_since(UNIX_EPOCH)?.as_secs()
-		    )
-		)).await?;
+                let players = up.players.clone().real().0.len();
+                if server.max_player_count > 0 && players as i64 >= server.max_player_count {
+                    if self.full.insert(name.to_string()) {
+                        events.push(Event::Full {
+                            players,
+                            max_players: server.max_player_count,
+                        });
+                    }
+                } else {
+                    self.full.remove(name);
+                }
+            }
+        }
 
-		let mut info = INFO.write().await;
-		let info = info.get_mut(&name).ok_or(format!("MapDataError: Unable to get server {name}"))?;
-		if let Info::ServerDown(down) = info {
-		    down.ping_sent = true;
-		} else {
-		    println!("server is back up with incredible timing");
-		}
-	    }
-	}
+        Ok(events)
     }
+}
+
+/// The alert channel only hears about outages
+async fn send_alert(
+    ctx: &serenity::Context,
+    alert: &Alert,
+    name: &str,
+    event: &Event,
+) -> Result<(), Error> {
+    let content = match event {
+        Event::Down { since } => format!(
+            "{} Server `{name}` went down <t:{since}:R>!",
+            alert.mentions()
+        ),
+        Event::Recovered => format!("Server `{name}` is back up!"),
+        _ => return Ok(()),
+    };
+
+    alert
+        .channel
+        .send_message(&ctx, CreateMessage::new().content(content))
+        .await?;
 
     Ok(())
 }
 
+pub async fn down_detector(ctx: &serenity::Context, detector: &mut Detector) -> Result<(), Error> {
+    let notified = check_servers(ctx, detector).await?;
+
+    if notified.is_empty() {
+        return Ok(());
+    }
+
+    let mut data = ctx.data.write().await;
+    let conn = data
+        .get_mut::<DbConnection>()
+        .ok_or("DataError: Unable to get database connection")?;
+
+    for (name, down) in notified {
+        db::write_notified(conn, &name, down).await?;
+    }
+
+    Ok(())
+}
+
+/// Send the events of every server, returns the servers whose down ping was sent (`true`) or
+/// who were announced back up (`false`)
+async fn check_servers(
+    ctx: &serenity::Context,
+    detector: &mut Detector,
+) -> Result<Vec<(String, bool)>, Error> {
+    // alerts wait on discord, so nothing is borrowed from `data` while they're sent
+    let (checks, alerts, webhooks): (Vec<(Server, Option<Info>)>, AlertsValue, WebhooksValue) = {
+        let data = ctx.data.read().await;
+
+        let pollers = data
+            .get::<Pollers>()
+            .ok_or("DataError: Unable to get pollers")?;
+        let servers = data
+            .get::<Servers>()
+            .ok_or("DataError: Unable to get servers")?;
+        let alerts = data
+            .get::<Alerts>()
+            .ok_or("DataError: Unable to get alerts")?;
+        let webhooks = data
+            .get::<Webhooks>()
+            .ok_or("DataError: Unable to get webhooks")?;
+
+        let checks = servers
+            .values()
+            .map(|server| {
+                (
+                    server.clone(),
+                    pollers.get(&server.name).and_then(Poller::latest),
+                )
+            })
+            .collect();
+
+        (checks, alerts.clone(), webhooks.clone())
+    };
+
+    detector.forget_deleted(|name| checks.iter().any(|(s, _)| s.name == name));
+
+    let mut notified = vec![];
+
+    for (server, info) in checks {
+        let name = &server.name;
+        // not polled yet
+        let Some(info) = info else {
+            continue;
+        };
+
+        for event in detector.observe(name, &server, &info)? {
+            match &event {
+                Event::Down { .. } => notified.push((name.clone(), true)),
+                Event::Recovered => notified.push((name.clone(), false)),
+                _ => (),
+            }
+
+            dispatch(&webhooks, name, &event);
+
+            if let Some(alert) = alerts.get(name).filter(|a| a.enabled) {
+                // one broken channel shouldn't stop alerts for the other servers
+                if let Err(e) = send_alert(ctx, alert, name, &event).await {
+                    eprintln!("Unable to send {} alert for {name}: {e}", event.name());
+                }
+            }
+        }
+    }
+
+    Ok(notified)
+}
+
+async fn load_detector(ctx: &serenity::Context) -> Result<Detector, Error> {
+    let mut data = ctx.data.write().await;
+    let conn = data
+        .get_mut::<DbConnection>()
+        .ok_or("DataError: Unable to get database connection")?;
+
+    Ok(Detector {
+        notified: db::read_notified(conn).await?,
+        ..Default::default()
+    })
+}
+
 pub async fn down_detector_loop(ctx: Arc<serenity::Context>) {
-    let mut interval = time::interval(Duration::from_secs(30));
+    let mut interval = time::interval(Duration::from_secs(10));
+    let mut detector = match load_detector(&ctx).await {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!(
+                "Unable to load down notifications, a server already down may be pinged again: {e}"
+            );
+            Detector::default()
+        }
+    };
 
     time::interval(Duration::from_secs(2)).tick().await;
     loop {
-	interval.tick().await;
+        interval.tick().await;
+
+        match down_detector(&ctx, &mut detector).await {
+            Ok(_) => (),
+            Err(e) => eprintln!("Error with down detector {e:?}"),
+        };
+    }
+}
+
+pub mod db {
+    use crate::Error;
+    use sqlx::SqliteConnection;
+    use std::collections::HashSet;
+
+    pub async fn read_notified(conn: &mut SqliteConnection) -> Result<HashSet<String>, Error> {
+        let rows = sqlx::query!("SELECT server_name FROM down_notifications")
+            .fetch_all(conn)
+            .await?;
+
+        Ok(rows.into_iter().map(|r| r.server_name).collect())
+    }
+
+    /// Remember whether the alert channel was told `server` is down
+    pub async fn write_notified(
+        conn: &mut SqliteConnection,
+        server: &str,
+        down: bool,
+    ) -> Result<(), Error> {
+        if down {
+            sqlx::query!(
+                "INSERT OR IGNORE INTO down_notifications (server_name) VALUES (?)",
+                server
+            )
+            .execute(conn)
+            .await?;
+        } else {
+            sqlx::query!(
+                "DELETE FROM down_notifications WHERE server_name = ?",
+                server
+            )
+            .execute(conn)
+            .await?;
+        }
+
+        Ok(())
+    }
+}
+
+fn set_down_detection_help() -> String {
+    "Tune when a server counts as down, so a dropped packet doesn't mark it down.
+Requires admin privileges."
+        .into()
+}
+
+#[poise::command(
+    slash_command,
+    check = "privilege_check",
+    help_text_fn = "set_down_detection_help"
+)]
+pub async fn set_down_detection(
+    ctx: Context<'_>,
+    #[description = "Server identifier"] name: String,
+    #[description = "Failed queries in a row before the server is down, keeps the current value if omitted"]
+    failures: Option<u32>,
+    #[description = "Successful queries in a row before it's back up, keeps the current value if omitted"]
+    recoveries: Option<u32>,
+    #[description = "Seconds down before anyone is pinged, keeps the current value if omitted"]
+    grace: Option<u32>,
+) -> Result<(), Error> {
+    let mut data = ctx.serenity_context().data.write().await;
+
+    let server = data
+        .get_mut::<Servers>()
+        .ok_or("DataError: Unable to get servers")?
+        .get_mut(&name)
+        .ok_or(format!("ServerError: Unable to get server {}", name))?;
+
+    if let Some(failures) = failures {
+        server.down_failures = failures.max(1) as i64;
+    }
+    if let Some(recoveries) = recoveries {
+        server.down_recoveries = recoveries.max(1) as i64;
+    }
+    if let Some(grace) = grace {
+        server.down_grace = grace as i64;
+    }
+
+    let server = server.clone();
+
+    let conn = data
+        .get_mut::<DbConnection>()
+        .ok_or("DataError: Unable to get database connection")?;
+    write_server(&server, conn).await?;
+
+    // thresholds are read when the poller starts, the grace period by the down detector
+    if failures.is_some() || recoveries.is_some() {
+        let pollers = data
+            .get_mut::<Pollers>()
+            .ok_or("DataError: Unable to get pollers")?;
+        start_poller(pollers, &server).await?;
+    }
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!(
+                "`{}` is down after {} failed queries, back up after {} successful ones, pings after {} seconds",
+                name, server.down_failures, server.down_recoveries, server.down_grace
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server_info::DownReason;
+    use crate::server_info::ServerDown;
+    use sqlx::{Connection, SqliteConnection};
+
+    fn server() -> Server {
+        Server {
+            name: "meow".into(),
+            addr: "127.0.0.1:27015".into(),
+            max_player_count: 0,
+            legacy: false,
+            allow_upload_required: false,
+            rcon_password: None,
+            poll_interval: 5,
+            down_failures: 3,
+            down_recoveries: 2,
+            down_grace: 0,
+        }
+    }
+
+    fn down() -> Info {
+        Info::ServerDown(ServerDown {
+            since: SystemTime::now() - Duration::from_secs(60),
+            reason: DownReason::Unreachable,
+        })
+    }
+
+    #[test]
+    fn pings_once() {
+        let mut detector = Detector::default();
+
+        let events = detector.observe("meow", &server(), &down()).unwrap();
+        assert!(matches!(events[..], [Event::Down { .. }]));
+
+        // still down on the next check
+        assert!(detector
+            .observe("meow", &server(), &down())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn remembers_notified() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&mut conn).await.unwrap();
+
+        db::write_notified(&mut conn, "meow", true).await.unwrap();
+        db::write_notified(&mut conn, "meow", true).await.unwrap();
+        db::write_notified(&mut conn, "woof", true).await.unwrap();
+        db::write_notified(&mut conn, "woof", false).await.unwrap();
+
+        // as if the bot restarted while meow was down
+        let mut detector = Detector {
+            notified: db::read_notified(&mut conn).await.unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(detector.notified, HashSet::from(["meow".to_string()]));
 
-	match down_detector(&ctx).await {
-	    Ok(_) => (),
-	    Err(e) => eprintln!("Error with down detector {e:?}"),
-	};
+        assert!(detector
+            .observe("meow", &server(), &down())
+            .unwrap()
+            .is_empty());
     }
 }
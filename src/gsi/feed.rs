@@ -0,0 +1,560 @@
+use super::model::{BombState, GameState, MapPhase, Player, RoundPhase, Team};
+use super::{GameStateIntegrationTokens, GameStates};
+use crate::Error;
+use ::serenity::all::{
+    ChannelId, Colour, CreateAttachment, CreateEmbed, CreateMessage, EditMessage, MessageId,
+    Timestamp,
+};
+use poise::serenity_prelude as serenity;
+use poise::serenity_prelude::prelude::TypeMapKey;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+use tokio::time;
+
+/// A feed message is edited at most this often, events in between are batched
+const EDIT_INTERVAL: Duration = Duration::from_secs(5);
+/// Events kept in the message, older ones scroll off
+const MAX_LINES: usize = 15;
+/// Discord's error code for a message that doesn't exist
+const UNKNOWN_MESSAGE: isize = 10008;
+
+/// Something worth telling the channel about
+#[derive(Debug, Clone, PartialEq)]
+pub enum FeedEvent {
+    MatchStarted,
+    RoundStarted {
+        round: u32,
+    },
+    RoundOver {
+        winner: Team,
+        reason: Option<String>,
+    },
+    Bomb(BombState),
+    Kill {
+        player: String,
+        kills: i32,
+        headshots: u32,
+    },
+    MatchOver,
+}
+
+impl fmt::Display for FeedEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeedEvent::MatchStarted => write!(f, "Match started"),
+            FeedEvent::RoundStarted { round } => write!(f, "Round {round} started"),
+            FeedEvent::RoundOver { winner, reason } => match reason {
+                Some(reason) => write!(f, "Round won by {winner} ({reason})"),
+                None => write!(f, "Round won by {winner}"),
+            },
+            FeedEvent::Bomb(state) => {
+                let state = format!("{state:?}").to_lowercase();
+                write!(f, "Bomb {state}")
+            }
+            FeedEvent::Kill {
+                player,
+                kills,
+                headshots,
+            } => {
+                match kills {
+                    1 => write!(f, "{player} got a kill")?,
+                    n => write!(f, "{player} got {n} kills")?,
+                }
+                match headshots {
+                    0 => Ok(()),
+                    1 if *kills == 1 => write!(f, " (headshot)"),
+                    n => write!(f, " ({n} headshots)"),
+                }
+            }
+            FeedEvent::MatchOver => write!(f, "Match over"),
+        }
+    }
+}
+
+/// Everyone in the payload by steam id, spectators get `allplayers`, players only themselves
+fn players(state: &GameState) -> HashMap<&str, &Player> {
+    let mut players: HashMap<&str, &Player> = state
+        .allplayers
+        .iter()
+        .flatten()
+        .map(|(id, p)| (id.as_str(), p))
+        .collect();
+
+    if let Some(
+        p @ Player {
+            steamid: Some(id), ..
+        },
+    ) = &state.player
+    {
+        players.entry(id).or_insert(p);
+    }
+
+    players
+}
+
+/// Whether `new` is a different match than `previous`, the feed starts a new message then
+pub fn new_match(previous: Option<&GameState>, new: &GameState) -> bool {
+    let (Some(old), Some(new)) = (previous.and_then(|p| p.map.as_ref()), new.map.as_ref()) else {
+        return new.map.is_some();
+    };
+
+    old.name != new.name || (old.phase == MapPhase::Gameover && new.phase != MapPhase::Gameover)
+}
+
+/// What happened between two payloads of the same client
+pub fn events(previous: Option<&GameState>, new: &GameState) -> Vec<FeedEvent> {
+    let mut events = vec![];
+
+    let Some(map) = &new.map else {
+        return events;
+    };
+    let previous = previous.filter(|_| !new_match(previous, new));
+    let old_map = previous.and_then(|p| p.map.as_ref());
+
+    let old_phase = old_map.map(|m| m.phase);
+    if old_phase != Some(map.phase) {
+        match map.phase {
+            MapPhase::Live if old_phase.is_some() => events.push(FeedEvent::MatchStarted),
+            MapPhase::Gameover => events.push(FeedEvent::MatchOver),
+            _ => (),
+        }
+    }
+
+    let old_round = previous.and_then(|p| p.round.as_ref());
+    if let Some(round) = &new.round {
+        if old_round.map(|r| r.phase) != Some(round.phase) {
+            match round.phase {
+                RoundPhase::Live if map.phase == MapPhase::Live => {
+                    events.push(FeedEvent::RoundStarted {
+                        round: map.round + 1,
+                    })
+                }
+                RoundPhase::Over => {
+                    if let Some(winner) = round.win_team {
+                        // the latest round is the one that just ended
+                        let reason = map.round_wins.as_ref().and_then(|w| {
+                            w.iter()
+                                .max_by_key(|(n, _)| n.parse::<u32>().unwrap_or(0))
+                                .map(|(_, r)| r.replace('_', " "))
+                        });
+                        events.push(FeedEvent::RoundOver { winner, reason });
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        match round.bomb {
+            Some(bomb) if old_round.and_then(|r| r.bomb) != Some(bomb) => {
+                events.push(FeedEvent::Bomb(bomb))
+            }
+            _ => (),
+        }
+    }
+
+    if let Some(previous) = previous {
+        let old_players = players(previous);
+        let same_round = old_map.map(|m| m.round) == Some(map.round);
+
+        let mut kills: Vec<FeedEvent> = players(new)
+            .into_iter()
+            .filter_map(|(id, p)| {
+                let old = old_players.get(id)?;
+                let stats = p.match_stats.as_ref()?;
+                let old_stats = old.match_stats.as_ref()?;
+
+                // stats reset between matches
+                let kills = stats.kills - old_stats.kills;
+                if kills <= 0 {
+                    return None;
+                }
+
+                // `round_killhs` starts over every round, so it only counts within one
+                let headshots = match (&p.state, &old.state) {
+                    (Some(s), Some(o)) if same_round => s
+                        .round_killhs
+                        .saturating_sub(o.round_killhs)
+                        .min(kills as u32),
+                    _ => 0,
+                };
+
+                Some(FeedEvent::Kill {
+                    player: p.name.clone().unwrap_or_else(|| id.to_string()),
+                    kills,
+                    headshots,
+                })
+            })
+            .collect();
+
+        // hashmap order isn't stable
+        kills.sort_by_key(|k| k.to_string());
+        events.extend(kills);
+    }
+
+    events
+}
+
+/// A live match feed of one token
+pub struct Feed {
+    pub channel: ChannelId,
+    message: Option<MessageId>,
+    lines: VecDeque<String>,
+    /// Bumped on every change, the message is up to date when it matches `sent`
+    version: u64,
+    sent: u64,
+    last_edit: Option<Instant>,
+}
+
+impl Feed {
+    pub fn new(channel: ChannelId) -> Self {
+        Feed {
+            channel,
+            message: None,
+            lines: VecDeque::new(),
+            version: 0,
+            sent: 0,
+            last_edit: None,
+        }
+    }
+
+    /// Apply a new payload, `previous` is the payload before it
+    pub fn update(&mut self, previous: Option<&GameState>, new: &GameState) {
+        if new_match(previous, new) {
+            self.message = None;
+            self.lines.clear();
+            self.version += 1;
+        }
+
+        for event in events(previous, new) {
+            if self.lines.len() >= MAX_LINES {
+                self.lines.pop_front();
+            }
+            self.lines.push_back(event.to_string());
+            self.version += 1;
+        }
+    }
+
+    fn due(&self) -> bool {
+        self.version != self.sent && self.last_edit.is_none_or(|t| t.elapsed() >= EDIT_INTERVAL)
+    }
+}
+
+pub struct Feeds;
+impl TypeMapKey for Feeds {
+    // token, feed
+    type Value = HashMap<String, Feed>;
+}
+
+pub fn make_feed_message(name: &str, state: &GameState, lines: &VecDeque<String>) -> CreateEmbed {
+    let mut embed = CreateEmbed::new();
+
+    let Some(map) = &state.map else {
+        return embed
+            .title(format!("{name} - not in a match"))
+            .thumbnail("attachment://respawnwcat.png");
+    };
+
+    embed = embed.title(format!("{name} - {}", map.name));
+
+    embed = match map.phase {
+        MapPhase::Live => embed.color(Colour::DARK_GREEN),
+        MapPhase::Gameover => embed.color(Colour::PURPLE),
+        _ => embed,
+    };
+
+    // danger zone has no teams or rounds
+    let score = if map.mode == "survival" {
+        "Danger Zone".to_string()
+    } else {
+        format!(
+            "CT {} - {} T, round {}",
+            map.team_ct.score,
+            map.team_t.score,
+            map.round + 1
+        )
+    };
+
+    embed = embed.description(format!(
+        r#"
+`{}` {}
+Events
+```
+{}
+```
+"#,
+        score,
+        match map.phase {
+            MapPhase::Warmup => "warmup",
+            MapPhase::Live => "live",
+            MapPhase::Intermission => "halftime",
+            MapPhase::Gameover => "match over",
+            MapPhase::Other => "",
+        },
+        // discord breaks formatting of codeblocks if it's empty
+        if lines.is_empty() {
+            " ".to_string()
+        } else {
+            lines.iter().cloned().collect::<Vec<_>>().join("\n")
+        }
+    ));
+
+    embed
+        .thumbnail("attachment://respawnwcat.png")
+        .timestamp(Timestamp::now())
+}
+
+/// Whether editing failed because the message was deleted, rather than discord having trouble
+fn message_gone(e: &serenity::Error) -> bool {
+    let serenity::Error::Http(serenity::HttpError::UnsuccessfulRequest(res)) = e else {
+        return false;
+    };
+
+    res.status_code == serenity::StatusCode::NOT_FOUND || res.error.code == UNKNOWN_MESSAGE
+}
+
+/// Post a new feed message, the thumbnail is attached to every one
+async fn send_feed(
+    ctx: &serenity::Context,
+    channel: ChannelId,
+    embed: CreateEmbed,
+) -> Result<MessageId, Error> {
+    let message = CreateMessage::new()
+        .embed(embed)
+        .add_file(CreateAttachment::path("static/respawnwcat.png").await?);
+
+    Ok(channel.send_message(ctx, message).await?.id)
+}
+
+async fn update_feeds(ctx: &serenity::Context) -> Result<(), Error> {
+    let due: Vec<(String, ChannelId, Option<MessageId>, u64, CreateEmbed)> = {
+        let data = ctx.data.read().await;
+
+        let feeds = data
+            .get::<Feeds>()
+            .ok_or("DataError: Unable to get feeds")?;
+        let states = data
+            .get::<GameStates>()
+            .ok_or("DataError: Unable to get gamestates")?;
+        let tokens = data
+            .get::<GameStateIntegrationTokens>()
+            .ok_or("DataError: Unable to get gamestate integration tokens")?;
+
+        feeds
+            .iter()
+            .filter(|(_, feed)| feed.due())
+            .filter_map(|(token, feed)| {
+                let embed =
+                    make_feed_message(tokens.get(token)?, &states.get(token)?.state, &feed.lines);
+                Some((
+                    token.clone(),
+                    feed.channel,
+                    feed.message,
+                    feed.version,
+                    embed,
+                ))
+            })
+            .collect()
+    };
+
+    for (token, channel, message, version, embed) in due {
+        let (sent, gone) = match message {
+            Some(id) => {
+                let edited = channel
+                    .edit_message(ctx, id, EditMessage::new().embed(embed))
+                    .await;
+                let gone = edited.as_ref().is_err_and(message_gone);
+
+                (edited.map(|m| m.id).map_err(Error::from), gone)
+            }
+            None => (send_feed(ctx, channel, embed).await, false),
+        };
+
+        let mut data = ctx.data.write().await;
+        let Some(feed) = data
+            .get_mut::<Feeds>()
+            .ok_or("DataError: Unable to get feeds")?
+            .get_mut(&token)
+        else {
+            continue;
+        };
+
+        feed.last_edit = Some(Instant::now());
+
+        match sent {
+            // a new match may have started while this was sent
+            Ok(id) if feed.message == message => {
+                feed.message = Some(id);
+                feed.sent = version;
+            }
+            Ok(_) => (),
+            // post a new message next time if it was deleted, otherwise edit it again
+            Err(e) => {
+                eprintln!("Unable to update gamestate feed in {channel}: {e}");
+                if gone && feed.message == message {
+                    feed.message = None;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn feed_loop(ctx: Arc<serenity::Context>) {
+    let mut interval = time::interval(Duration::from_secs(1));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = update_feeds(&ctx).await {
+            eprintln!("Error with gamestate feeds {e:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn state(value: serde_json::Value) -> GameState {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn competitive(map_phase: &str, round: u32, round_phase: &str, kills: i32) -> GameState {
+        state(json!({
+            "map": {"mode": "competitive", "name": "de_mirage", "phase": map_phase, "round": round,
+                    "round_wins": {"1": "t_win_elimination", "2": "ct_win_defuse"}},
+            "round": {"phase": round_phase, "win_team": "CT"},
+            "player": {"steamid": "1", "name": "tom",
+                       "state": {"round_killhs": kills},
+                       "match_stats": {"kills": kills}}
+        }))
+    }
+
+    #[test]
+    fn round_transitions() {
+        let freeze = competitive("live", 2, "freezetime", 0);
+        let live = competitive("live", 2, "live", 0);
+        let over = competitive("live", 2, "over", 0);
+
+        assert_eq!(
+            events(Some(&freeze), &live),
+            vec![FeedEvent::RoundStarted { round: 3 }]
+        );
+        assert_eq!(
+            events(Some(&live), &over),
+            vec![FeedEvent::RoundOver {
+                winner: Team::CounterTerrorist,
+                reason: Some("ct win defuse".into())
+            }]
+        );
+        assert!(events(Some(&live), &live).is_empty());
+    }
+
+    #[test]
+    fn kills() {
+        let before = competitive("live", 2, "live", 1);
+        let after = competitive("live", 2, "live", 3);
+
+        assert_eq!(
+            events(Some(&before), &after),
+            vec![FeedEvent::Kill {
+                player: "tom".into(),
+                kills: 2,
+                headshots: 2
+            }]
+        );
+        assert_eq!(
+            events(Some(&before), &after)[0].to_string(),
+            "tom got 2 kills (2 headshots)"
+        );
+    }
+
+    #[test]
+    fn headshots_within_round() {
+        let before = competitive("live", 2, "live", 1);
+        // a kill that isn't a headshot, `round_killhs` is set by `competitive`
+        let mut after = competitive("live", 2, "live", 2);
+        after
+            .player
+            .as_mut()
+            .unwrap()
+            .state
+            .as_mut()
+            .unwrap()
+            .round_killhs = 1;
+
+        assert_eq!(
+            events(Some(&before), &after),
+            vec![FeedEvent::Kill {
+                player: "tom".into(),
+                kills: 1,
+                headshots: 0
+            }]
+        );
+
+        // the count started over, so it can't be compared to the last round's
+        let next = competitive("live", 3, "live", 3);
+        assert_eq!(
+            events(Some(&before), &next),
+            vec![FeedEvent::Kill {
+                player: "tom".into(),
+                kills: 2,
+                headshots: 0
+            }]
+        );
+    }
+
+    #[test]
+    fn match_lifecycle() {
+        let warmup = competitive("warmup", 0, "live", 0);
+        let live = competitive("live", 0, "freezetime", 0);
+        let over = competitive("gameover", 24, "over", 0);
+
+        assert_eq!(events(Some(&warmup), &live), vec![FeedEvent::MatchStarted]);
+        assert!(events(Some(&over), &over).is_empty());
+        assert_eq!(
+            events(Some(&live), &over),
+            vec![
+                FeedEvent::MatchOver,
+                FeedEvent::RoundOver {
+                    winner: Team::CounterTerrorist,
+                    reason: Some("ct win defuse".into())
+                }
+            ]
+        );
+
+        assert!(!new_match(Some(&warmup), &live));
+        assert!(new_match(Some(&over), &warmup));
+        // a previous match says nothing about this one
+        assert_eq!(events(Some(&over), &warmup), vec![]);
+    }
+
+    #[test]
+    fn feed_scrolls_and_resets() {
+        let mut feed = Feed::new(ChannelId::new(1));
+        let mut previous = competitive("live", 0, "live", 0);
+
+        for kills in 1..=(MAX_LINES as i32 + 5) {
+            let new = competitive("live", 0, "live", kills);
+            feed.update(Some(&previous), &new);
+            previous = new;
+        }
+
+        assert_eq!(feed.lines.len(), MAX_LINES);
+        assert!(feed.due());
+
+        let over = competitive("gameover", 0, "over", MAX_LINES as i32 + 5);
+        feed.update(Some(&previous), &over);
+        feed.message = Some(MessageId::new(1));
+
+        feed.update(Some(&over), &competitive("warmup", 0, "live", 0));
+        assert!(feed.message.is_none());
+        assert!(feed.lines.is_empty());
+    }
+}
@@ -0,0 +1,521 @@
+pub mod feed;
+pub mod model;
+
+use crate::db::DbConnection;
+use crate::privilege_check;
+use crate::settings::Settings;
+use crate::{Context, Error};
+use ::serenity::all::CreateAttachment;
+use feed::Feed;
+use feed::Feeds;
+use model::GameState;
+use poise::serenity_prelude as serenity;
+use poise::serenity_prelude::prelude::TypeMapKey;
+use poise::CreateReply;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// Clients post several times a second, the stored snapshot is only updated this often
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+/// Clients send a heartbeat every 10 seconds, a payload older than this is from another session
+const STALE_AFTER: Duration = Duration::from_secs(60);
+
+pub struct GameStateIntegrationTokens;
+impl TypeMapKey for GameStateIntegrationTokens {
+    // token, name
+    type Value = HashMap<String, String>;
+}
+
+/// Latest payload of a token
+#[derive(Debug, Clone)]
+pub struct LiveState {
+    pub state: GameState,
+    pub received: SystemTime,
+    flushed: Option<SystemTime>,
+}
+
+pub struct GameStates;
+impl TypeMapKey for GameStates {
+    // token, state
+    type Value = HashMap<String, LiveState>;
+}
+
+pub enum Received {
+    Stored,
+    MissingToken,
+    UnknownToken,
+}
+
+/// Keep `state` as the latest payload of its token
+pub async fn receive(ctx: &serenity::Context, state: GameState) -> Result<Received, Error> {
+    let Some(token) = state.auth.as_ref().map(|a| a.token.clone()) else {
+        return Ok(Received::MissingToken);
+    };
+
+    let mut data = ctx.data.write().await;
+
+    if !data
+        .get::<GameStateIntegrationTokens>()
+        .ok_or("DataError: Unable to get gamestate integration tokens")?
+        .contains_key(&token)
+    {
+        return Ok(Received::UnknownToken);
+    }
+
+    let now = SystemTime::now();
+
+    let previous = data
+        .get_mut::<GameStates>()
+        .ok_or("DataError: Unable to get gamestates")?
+        .remove(&token);
+
+    let flushed = previous.as_ref().and_then(|s| s.flushed);
+    let flush = match flushed {
+        Some(f) => now.duration_since(f).unwrap_or_default() >= FLUSH_INTERVAL,
+        None => true,
+    };
+
+    let live = LiveState {
+        state,
+        received: now,
+        flushed: if flush { Some(now) } else { flushed },
+    };
+
+    // a client that went quiet has restarted or left, whatever it sends now is a new match
+    let previous = previous
+        .filter(|p| now.duration_since(p.received).unwrap_or_default() < STALE_AFTER)
+        .map(|p| p.state);
+
+    if let Some(feed) = data
+        .get_mut::<Feeds>()
+        .ok_or("DataError: Unable to get feeds")?
+        .get_mut(&token)
+    {
+        feed.update(previous.as_ref(), &live.state);
+    }
+
+    let written = if flush {
+        let conn = data
+            .get_mut::<DbConnection>()
+            .ok_or("DataError: Unable to get database connection")?;
+        db::write_snapshot(conn, &token, &live).await
+    } else {
+        Ok(())
+    };
+
+    data.get_mut::<GameStates>()
+        .ok_or("DataError: Unable to get gamestates")?
+        .insert(token, live);
+
+    written?;
+
+    Ok(Received::Stored)
+}
+
+const TOKEN_LENGTH: usize = 32;
+
+/// Config for the client's `csgo/cfg` folder, CS:GO reads every `gamestate_integration_*.cfg`
+fn config_file(uri: &str, token: &str) -> String {
+    format!(
+        r#""meowdz"
+{{
+    "uri" "{uri}"
+    "timeout" "5.0"
+    "buffer" "0.1"
+    "throttle" "0.5"
+    "heartbeat" "10.0"
+    "auth"
+    {{
+        "token" "{token}"
+    }}
+    "data"
+    {{
+        "provider" "1"
+        "map" "1"
+        "map_round_wins" "1"
+        "round" "1"
+        "player_id" "1"
+        "player_state" "1"
+        "player_weapons" "1"
+        "player_match_stats" "1"
+        "player_position" "1"
+        "allplayers_id" "1"
+        "allplayers_state" "1"
+        "allplayers_match_stats" "1"
+        "allplayers_weapons" "1"
+        "allplayers_position" "1"
+        "phase_countdowns" "1"
+        "bomb" "1"
+    }}
+}}
+"#
+    )
+}
+
+fn gsi_help() -> String {
+    "Manage Game State Integration tokens, a CS:GO client with the generated config \
+sends its game state to the bot while playing or spectating.
+Put the file in `csgo/cfg` and restart the game.
+`/gsi feed` posts rounds, kills and results of that client's matches into a channel, \
+editing one message per match.
+Requires admin privileges."
+        .into()
+}
+
+#[poise::command(
+    slash_command,
+    subcommands("create", "revoke", "list", "feed"),
+    check = "privilege_check",
+    category = "GSI",
+    help_text_fn = "gsi_help"
+)]
+pub async fn gsi(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Create a token and get the config file for it
+#[poise::command(slash_command, check = "privilege_check", category = "GSI")]
+async fn create(
+    ctx: Context<'_>,
+    #[description = "Who or what the token is for"] name: String,
+) -> Result<(), Error> {
+    let mut data = ctx.serenity_context().data.write().await;
+
+    // the webserver is what the redirector address points at
+    let uri = data
+        .get::<Settings>()
+        .ok_or("DataError: Unable to get settings")?
+        .external_redirector_address
+        .clone()
+        .ok_or("The external address isn't set, set it with /set_external_redirector")?;
+    let uri = format!("{}/", uri.trim_end_matches('/'));
+
+    let tokens = data
+        .get_mut::<GameStateIntegrationTokens>()
+        .ok_or("DataError: Unable to get gamestate integration tokens")?;
+
+    if tokens.values().any(|n| *n == name) {
+        return Err(format!("A token called {name} already exists").into());
+    }
+
+    let token: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect();
+
+    tokens.insert(token.clone(), name.clone());
+
+    let conn = data
+        .get_mut::<DbConnection>()
+        .ok_or("DataError: Unable to get database connection")?;
+    db::insert_token(conn, &token, &name).await?;
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!(
+                "Created token `{name}`, put this file in `csgo/cfg` and restart the game"
+            ))
+            .attachment(CreateAttachment::bytes(
+                config_file(&uri, &token),
+                "gamestate_integration_meowdz.cfg",
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Revoke a token, the client's payloads are refused from now on
+#[poise::command(slash_command, check = "privilege_check", category = "GSI")]
+async fn revoke(
+    ctx: Context<'_>,
+    #[description = "Name of the token"] name: String,
+) -> Result<(), Error> {
+    let mut data = ctx.serenity_context().data.write().await;
+
+    let tokens = data
+        .get_mut::<GameStateIntegrationTokens>()
+        .ok_or("DataError: Unable to get gamestate integration tokens")?;
+
+    let token = tokens
+        .iter()
+        .find(|(_, n)| **n == name)
+        .map(|(t, _)| t.clone())
+        .ok_or(format!("No token called {name}"))?;
+    tokens.remove(&token);
+
+    data.get_mut::<GameStates>()
+        .ok_or("DataError: Unable to get gamestates")?
+        .remove(&token);
+    data.get_mut::<Feeds>()
+        .ok_or("DataError: Unable to get feeds")?
+        .remove(&token);
+
+    let conn = data
+        .get_mut::<DbConnection>()
+        .ok_or("DataError: Unable to get database connection")?;
+    db::remove_token(conn, &token).await?;
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!("Revoked token `{name}`"))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// List tokens and when they last sent anything
+#[poise::command(slash_command, check = "privilege_check", category = "GSI")]
+async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    let data = ctx.serenity_context().data.read().await;
+
+    let tokens = data
+        .get::<GameStateIntegrationTokens>()
+        .ok_or("DataError: Unable to get gamestate integration tokens")?;
+    let states = data
+        .get::<GameStates>()
+        .ok_or("DataError: Unable to get gamestates")?;
+
+    let mut names: Vec<(&String, Option<u64>)> = tokens
+        .iter()
+        .map(|(token, name)| {
+            let received = states
+                .get(token)
+                .and_then(|s| s.received.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs());
+            (name, received)
+        })
+        .collect();
+    names.sort();
+
+    let list = names
+        .iter()
+        .fold(String::new(), |mut output, (name, received)| {
+            _ = match received {
+                Some(t) => writeln!(output, "`{name}` - last heard from <t:{t}:R>"),
+                None => writeln!(output, "`{name}` - never heard from"),
+            };
+            output
+        });
+
+    ctx.send(
+        CreateReply::default()
+            .content(if list.is_empty() {
+                "No tokens, create one with /gsi create".to_string()
+            } else {
+                list
+            })
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Post a live feed of what a token's client sees, one message per match that's kept up to date
+#[poise::command(slash_command, check = "privilege_check", category = "GSI")]
+async fn feed(
+    ctx: Context<'_>,
+    #[description = "Name of the token"] name: String,
+    #[description = "Defaults to this channel"] channel: Option<serenity::GuildChannel>,
+    #[description = "Post the feed at all"] enabled: Option<bool>,
+) -> Result<(), Error> {
+    let mut data = ctx.serenity_context().data.write().await;
+
+    let token = data
+        .get::<GameStateIntegrationTokens>()
+        .ok_or("DataError: Unable to get gamestate integration tokens")?
+        .iter()
+        .find(|(_, n)| **n == name)
+        .map(|(t, _)| t.clone())
+        .ok_or(format!("No token called {name}"))?;
+
+    let channel = enabled
+        .unwrap_or(true)
+        .then(|| channel.map(|c| c.id).unwrap_or(ctx.channel_id()));
+
+    let feeds = data
+        .get_mut::<Feeds>()
+        .ok_or("DataError: Unable to get feeds")?;
+    match channel {
+        Some(channel) => feeds.insert(token.clone(), Feed::new(channel)),
+        None => feeds.remove(&token),
+    };
+
+    let conn = data
+        .get_mut::<DbConnection>()
+        .ok_or("DataError: Unable to get database connection")?;
+    db::set_feed_channel(conn, &token, channel).await?;
+
+    ctx.send(
+        CreateReply::default()
+            .content(match channel {
+                Some(channel) => format!("The live feed of `{name}` goes to <#{channel}>"),
+                None => format!("Stopped the live feed of `{name}`"),
+            })
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub mod db {
+    use super::model::GameState;
+    use super::Feed;
+    use super::LiveState;
+    use crate::Error;
+    use poise::serenity_prelude::ChannelId;
+    use sqlx::SqliteConnection;
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use std::time::UNIX_EPOCH;
+
+    pub async fn read_tokens(
+        conn: &mut SqliteConnection,
+    ) -> Result<HashMap<String, String>, Error> {
+        let tokens = sqlx::query!(r#"SELECT token AS "token!", name FROM gamestate_integration"#)
+            .fetch_all(conn)
+            .await?;
+
+        Ok(tokens.into_iter().map(|v| (v.token, v.name)).collect())
+    }
+
+    pub async fn insert_token(
+        conn: &mut SqliteConnection,
+        token: &str,
+        name: &str,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            "INSERT INTO gamestate_integration (token, name) VALUES (?, ?)",
+            token,
+            name
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn read_feeds(conn: &mut SqliteConnection) -> Result<HashMap<String, Feed>, Error> {
+        let feeds = sqlx::query!(
+            r#"SELECT token AS "token!", feed_channel_id AS "feed_channel_id!"
+FROM gamestate_integration
+WHERE feed_channel_id IS NOT NULL"#
+        )
+        .fetch_all(conn)
+        .await?;
+
+        Ok(feeds
+            .into_iter()
+            .filter_map(|v| {
+                let channel = ChannelId::new(v.feed_channel_id.parse().ok()?);
+                Some((v.token, Feed::new(channel)))
+            })
+            .collect())
+    }
+
+    pub async fn set_feed_channel(
+        conn: &mut SqliteConnection,
+        token: &str,
+        channel: Option<ChannelId>,
+    ) -> Result<(), Error> {
+        let channel = channel.map(|c| c.to_string());
+
+        sqlx::query!(
+            "UPDATE gamestate_integration SET feed_channel_id = ? WHERE token = ?",
+            channel,
+            token
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// The token's snapshot goes with it
+    pub async fn remove_token(conn: &mut SqliteConnection, token: &str) -> Result<(), Error> {
+        sqlx::query!("DELETE FROM gamestate_snapshots WHERE token = ?", token)
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query!("DELETE FROM gamestate_integration WHERE token = ?", token)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn read_snapshots(
+        conn: &mut SqliteConnection,
+    ) -> Result<HashMap<String, LiveState>, Error> {
+        let snapshots = sqlx::query!("SELECT token, state, received FROM gamestate_snapshots")
+            .fetch_all(conn)
+            .await?;
+
+        Ok(snapshots
+            .into_iter()
+            .filter_map(|v| {
+                // snapshots from an older model are dropped rather than failing startup
+                let state: GameState = serde_json::from_str(&v.state)
+                    .inspect_err(|e| eprintln!("Unable to load gamestate of {}: {e}", v.token))
+                    .ok()?;
+                let received = UNIX_EPOCH + Duration::from_secs(v.received.max(0) as u64);
+
+                Some((
+                    v.token,
+                    LiveState {
+                        state,
+                        received,
+                        flushed: Some(received),
+                    },
+                ))
+            })
+            .collect())
+    }
+
+    pub async fn write_snapshot(
+        conn: &mut SqliteConnection,
+        token: &str,
+        live: &LiveState,
+    ) -> Result<(), Error> {
+        let state = serde_json::to_string(&live.state)?;
+        let received = live.received.duration_since(UNIX_EPOCH)?.as_secs() as i64;
+
+        sqlx::query!(
+            "INSERT INTO gamestate_snapshots (token, state, received) VALUES (?, ?, ?)
+ON CONFLICT(token) DO UPDATE
+SET state = excluded.state,
+    received = excluded.received",
+            token,
+            state,
+            received
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config() {
+        let cfg = config_file("https://dz.kotiboksi.xyz/", "abc123");
+
+        assert!(cfg.contains(r#""uri" "https://dz.kotiboksi.xyz/""#));
+        assert!(cfg.contains(r#""token" "abc123""#));
+        assert_eq!(cfg.matches('{').count(), cfg.matches('}').count());
+    }
+}
@@ -0,0 +1,495 @@
+//! Payload CS:GO posts to the bot when Game State Integration is set up, see
+//! https://developer.valvesoftware.com/wiki/Counter-Strike:_Global_Offensive_Game_State_Integration
+//!
+//! Clients only send the components enabled in their config, so every component is optional.
+//! Everything also serializes back into the same shape, that's how snapshots are stored.
+
+use serde::de;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GameState {
+    pub provider: Option<Provider>,
+    pub map: Option<Map>,
+    pub round: Option<Round>,
+    /// The player whose client sent this, or whoever they're spectating
+    pub player: Option<Player>,
+    /// Only sent to spectators and GOTV, keyed by steam id
+    pub allplayers: Option<BTreeMap<String, Player>>,
+    pub phase_countdowns: Option<PhaseCountdowns>,
+    pub bomb: Option<Bomb>,
+    /// Never stored, the token is what a snapshot is stored under
+    #[serde(skip_serializing)]
+    pub auth: Option<Auth>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Auth {
+    pub token: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Provider {
+    pub name: String,
+    pub appid: u32,
+    pub version: u32,
+    /// The client sending the payload
+    pub steamid: String,
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MapPhase {
+    Warmup,
+    Live,
+    Intermission,
+    Gameover,
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Map {
+    /// competitive, casual, survival for Danger Zone...
+    pub mode: String,
+    pub name: String,
+    pub phase: MapPhase,
+    #[serde(default)]
+    pub round: u32,
+    #[serde(default)]
+    pub team_ct: TeamStats,
+    #[serde(default)]
+    pub team_t: TeamStats,
+    #[serde(default)]
+    pub num_matches_to_win_series: u32,
+    #[serde(default)]
+    pub current_spectators: u32,
+    #[serde(default)]
+    pub souvenirs_total: u32,
+    /// Round number, how it was won like `ct_win_elimination`
+    pub round_wins: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TeamStats {
+    pub name: Option<String>,
+    pub score: u32,
+    pub consecutive_round_losses: u32,
+    pub timeouts_remaining: u32,
+    pub matches_won_this_series: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RoundPhase {
+    Freezetime,
+    Live,
+    Over,
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Team {
+    #[serde(rename = "CT")]
+    CounterTerrorist,
+    #[serde(rename = "T")]
+    Terrorist,
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BombState {
+    Carried,
+    Dropped,
+    Planting,
+    Planted,
+    Defusing,
+    Defused,
+    Exploded,
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Round {
+    pub phase: RoundPhase,
+    pub win_team: Option<Team>,
+    /// Only sent once the bomb is planted
+    pub bomb: Option<BombState>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Activity {
+    Playing,
+    Menu,
+    Textinput,
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Player {
+    /// Missing in `allplayers`, where it's the key instead
+    pub steamid: Option<String>,
+    /// Only sent with the `player_id` component
+    pub name: Option<String>,
+    pub clan: Option<String>,
+    pub observer_slot: Option<u8>,
+    pub team: Option<Team>,
+    pub activity: Option<Activity>,
+    pub state: Option<PlayerState>,
+    pub match_stats: Option<MatchStats>,
+    #[serde(default)]
+    pub weapons: BTreeMap<String, Weapon>,
+    pub position: Option<Vector>,
+    pub forward: Option<Vector>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlayerState {
+    pub health: u32,
+    pub armor: u32,
+    pub helmet: bool,
+    pub defusekit: Option<bool>,
+    /// How blinded, smoked or burning the player is, 0 to 255
+    pub flashed: u32,
+    pub smoked: u32,
+    pub burning: u32,
+    pub money: u32,
+    pub round_kills: u32,
+    pub round_killhs: u32,
+    pub round_totaldmg: u32,
+    pub equip_value: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MatchStats {
+    pub kills: i32,
+    pub assists: i32,
+    pub deaths: i32,
+    pub mvps: i32,
+    pub score: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Weapon {
+    pub name: String,
+    pub paintkit: Option<String>,
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    /// active, holstered or reloading
+    pub state: String,
+    pub ammo_clip: Option<u32>,
+    pub ammo_clip_max: Option<u32>,
+    pub ammo_reserve: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PhaseCountdowns {
+    /// freezetime, live, bomb, defuse, over...
+    pub phase: String,
+    #[serde(deserialize_with = "float")]
+    pub phase_ends_in: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bomb {
+    pub state: BombState,
+    pub position: Option<Vector>,
+    #[serde(default, deserialize_with = "optional_float")]
+    pub countdown: Option<f32>,
+    /// Steam id of the carrier, planter or defuser
+    pub player: Option<String>,
+}
+
+/// CS:GO sends positions as `"x, y, z"`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vector {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Serialize for Vector {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{}, {}, {}", self.x, self.y, self.z))
+    }
+}
+
+impl<'de> Deserialize<'de> for Vector {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        let mut parts = s.split(',').map(|p| p.trim().parse::<f32>());
+
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(Ok(x)), Some(Ok(y)), Some(Ok(z)), None) => Ok(Vector { x, y, z }),
+            _ => Err(de::Error::invalid_value(
+                de::Unexpected::Str(&s),
+                &"three comma separated numbers",
+            )),
+        }
+    }
+}
+
+/// Countdowns come as strings like `"9.4"`, stored snapshots have plain numbers
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Float {
+    Number(f32),
+    String(String),
+}
+
+impl Float {
+    fn value<E: de::Error>(self) -> Result<f32, E> {
+        match self {
+            Float::Number(v) => Ok(v),
+            Float::String(s) => s.trim().parse().map_err(de::Error::custom),
+        }
+    }
+}
+
+fn float<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+    Float::deserialize(deserializer)?.value()
+}
+
+fn optional_float<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f32>, D::Error> {
+    Option::<Float>::deserialize(deserializer)?
+        .map(Float::value)
+        .transpose()
+}
+
+impl fmt::Display for Team {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Team::CounterTerrorist => "CT",
+            Team::Terrorist => "T",
+            Team::Other => "?",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // trimmed down payload of a spectating client
+    const PAYLOAD: &str = r#"{
+	"provider": {
+		"name": "Counter-Strike: Global Offensive",
+		"appid": 730,
+		"version": 13881,
+		"steamid": "76561198000000000",
+		"timestamp": 1700000000
+	},
+	"map": {
+		"mode": "competitive",
+		"name": "de_mirage",
+		"phase": "live",
+		"round": 3,
+		"team_ct": {
+			"score": 2,
+			"consecutive_round_losses": 0,
+			"timeouts_remaining": 1,
+			"matches_won_this_series": 0
+		},
+		"team_t": {
+			"score": 1,
+			"consecutive_round_losses": 2,
+			"timeouts_remaining": 1,
+			"matches_won_this_series": 0
+		},
+		"num_matches_to_win_series": 0,
+		"current_spectators": 1,
+		"souvenirs_total": 0,
+		"round_wins": {
+			"1": "t_win_elimination",
+			"2": "ct_win_defuse",
+			"3": "ct_win_time"
+		}
+	},
+	"round": {
+		"phase": "live",
+		"bomb": "planted"
+	},
+	"player": {
+		"steamid": "76561198000000001",
+		"clan": "meow",
+		"name": "tom",
+		"observer_slot": 1,
+		"team": "CT",
+		"activity": "playing",
+		"state": {
+			"health": 87,
+			"armor": 100,
+			"helmet": true,
+			"defusekit": true,
+			"flashed": 0,
+			"smoked": 0,
+			"burning": 0,
+			"money": 1450,
+			"round_kills": 1,
+			"round_killhs": 1,
+			"round_totaldmg": 100,
+			"equip_value": 4950
+		},
+		"match_stats": {
+			"kills": 4,
+			"assists": 1,
+			"deaths": 2,
+			"mvps": 1,
+			"score": 11
+		},
+		"weapons": {
+			"weapon_0": {
+				"name": "weapon_knife",
+				"paintkit": "default",
+				"type": "Knife",
+				"state": "holstered"
+			},
+			"weapon_1": {
+				"name": "weapon_m4a1",
+				"paintkit": "default",
+				"type": "Rifle",
+				"ammo_clip": 22,
+				"ammo_clip_max": 30,
+				"ammo_reserve": 90,
+				"state": "active"
+			}
+		},
+		"position": "-1247.63, -1537.05, -167.97",
+		"forward": "0.98, -0.17, -0.01"
+	},
+	"allplayers": {
+		"76561198000000002": {
+			"name": "jerry",
+			"observer_slot": 6,
+			"team": "T",
+			"state": {
+				"health": 0,
+				"armor": 0,
+				"helmet": false,
+				"flashed": 0,
+				"burning": 0,
+				"money": 3200,
+				"round_kills": 0,
+				"round_killhs": 0,
+				"round_totaldmg": 0,
+				"equip_value": 200
+			},
+			"match_stats": {
+				"kills": 2,
+				"assists": 0,
+				"deaths": 4,
+				"mvps": 0,
+				"score": 6
+			},
+			"weapons": {},
+			"position": "0.00, 0.00, 0.00",
+			"forward": "1.00, 0.00, 0.00"
+		}
+	},
+	"phase_countdowns": {
+		"phase": "bomb",
+		"phase_ends_in": "31.9"
+	},
+	"bomb": {
+		"state": "planted",
+		"position": "-300.12, -2100.50, -160.00",
+		"countdown": "31.9"
+	},
+	"previously": {
+		"round": {
+			"bomb": null
+		}
+	},
+	"auth": {
+		"token": "meow"
+	}
+}"#;
+
+    #[test]
+    fn full_payload() {
+        let state: GameState = serde_json::from_str(PAYLOAD).unwrap();
+
+        assert_eq!(state.auth.as_ref().unwrap().token, "meow");
+
+        let map = state.map.as_ref().unwrap();
+        assert_eq!(map.phase, MapPhase::Live);
+        assert_eq!((map.team_ct.score, map.team_t.score), (2, 1));
+
+        assert_eq!(state.round.as_ref().unwrap().bomb, Some(BombState::Planted));
+
+        let player = state.player.as_ref().unwrap();
+        assert_eq!(player.team, Some(Team::CounterTerrorist));
+        assert_eq!(player.state.as_ref().unwrap().health, 87);
+        assert_eq!(player.match_stats.as_ref().unwrap().kills, 4);
+        assert_eq!(player.weapons["weapon_1"].kind.as_deref(), Some("Rifle"));
+        assert_eq!(player.position.unwrap().x, -1247.63);
+
+        let jerry = &state.allplayers.as_ref().unwrap()["76561198000000002"];
+        assert_eq!(jerry.team, Some(Team::Terrorist));
+        assert_eq!(jerry.state.as_ref().unwrap().smoked, 0);
+
+        assert_eq!(state.phase_countdowns.as_ref().unwrap().phase_ends_in, 31.9);
+        assert_eq!(state.bomb.as_ref().unwrap().countdown, Some(31.9));
+    }
+
+    #[test]
+    fn partial_payload() {
+        let state: GameState = serde_json::from_str(
+            r#"{"provider": {"name": "Counter-Strike: Global Offensive", "appid": 730,
+                "version": 13881, "steamid": "1", "timestamp": 1},
+                "map": {"mode": "survival", "name": "dz_blacksite", "phase": "warmup"},
+                "round": {"phase": "somethingnew"}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(state.map.unwrap().round, 0);
+        assert_eq!(state.round.unwrap().phase, RoundPhase::Other);
+        assert!(state.player.is_none());
+    }
+
+    #[test]
+    fn player_without_id() {
+        let state: GameState = serde_json::from_str(
+            r#"{"provider": {"name": "Counter-Strike: Global Offensive", "appid": 730,
+                "version": 13881, "steamid": "1", "timestamp": 1},
+                "player": {"state": {"health": 100, "armor": 0, "helmet": false}}}"#,
+        )
+        .unwrap();
+
+        let player = state.player.unwrap();
+        assert_eq!(player.name, None);
+        assert_eq!(player.state.unwrap().health, 100);
+    }
+
+    #[test]
+    fn round_trip() {
+        let state: GameState = serde_json::from_str(PAYLOAD).unwrap();
+
+        let stored = serde_json::to_string(&state).unwrap();
+        assert!(!stored.contains("auth"));
+
+        let loaded: GameState = serde_json::from_str(&stored).unwrap();
+        assert_eq!(
+            loaded,
+            GameState {
+                auth: None,
+                ..state
+            }
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use super::db::Sample;
+use super::SAMPLE_INTERVAL;
+use crate::Error;
+use plotters::prelude::*;
+use std::io::Cursor;
+use std::sync::Once;
+
+const CHART_SIZE: (u32, u32) = (1000, 500);
+const BACKGROUND: RGBColor = RGBColor(0x2B, 0x2D, 0x31);
+const FOREGROUND: RGBColor = RGBColor(0xDB, 0xDE, 0xE1);
+const PLAYERS: RGBColor = RGBColor(0x57, 0xF2, 0x87);
+const BOTS: RGBColor = RGBColor(0x94, 0x9B, 0xA4);
+const MAP_CHANGE: RGBColor = RGBColor(0xFE, 0xE7, 0x5C);
+
+static FONT: &[u8] = include_bytes!("../../fonts/DejaVuSans.ttf");
+static REGISTER_FONT: Once = Once::new();
+
+/// Format a point in time relative to `now`
+fn ago(secs: i64) -> String {
+    match secs {
+        ..=0 => "now".into(),
+        s if s >= 2 * 86400 => format!("{}d ago", s / 86400),
+        s if s >= 3600 => format!("{}h ago", s / 3600),
+        s => format!("{}m ago", s / 60),
+    }
+}
+
+/// Split samples into runs without gaps, so downtime isn't drawn as a line
+fn runs(samples: &[Sample]) -> Vec<&[Sample]> {
+    samples
+        .chunk_by(|a, b| b.time - a.time <= 3 * SAMPLE_INTERVAL)
+        .collect()
+}
+
+/// Samples where the map differs from the one before
+fn map_changes(samples: &[Sample]) -> Vec<&Sample> {
+    let mut changes = vec![];
+    let mut map = None;
+
+    for sample in samples {
+        if map != Some(&sample.map) {
+            map = Some(&sample.map);
+            changes.push(sample);
+        }
+    }
+
+    changes
+}
+
+/// Render a PNG line chart of player counts between `since` and `now`
+pub fn render_chart(
+    name: &str,
+    samples: &[Sample],
+    since: i64,
+    now: i64,
+) -> Result<Vec<u8>, Error> {
+    REGISTER_FONT.call_once(|| {
+        if plotters::style::register_font("sans-serif", FontStyle::Normal, FONT).is_err() {
+            eprintln!("Unable to load chart font");
+        }
+    });
+
+    let (width, height) = CHART_SIZE;
+    let mut buf = vec![0; (width * height * 3) as usize];
+
+    {
+        let root = BitMapBackend::with_buffer(&mut buf, CHART_SIZE).into_drawing_area();
+        root.fill(&BACKGROUND)?;
+
+        let max = samples
+            .iter()
+            .map(|s| s.max_players.max(s.players + s.bots))
+            .max()
+            .unwrap_or(0)
+            + 1;
+
+        let text = ("sans-serif", 16).into_font().color(&FOREGROUND);
+
+        let mut chart = ChartBuilder::on(&root)
+            .caption(
+                format!("Players on {name}"),
+                ("sans-serif", 24).into_font().color(&FOREGROUND),
+            )
+            .margin(20)
+            .x_label_area_size(30)
+            .y_label_area_size(40)
+            .build_cartesian_2d(since..now, 0..max)?;
+
+        chart
+            .configure_mesh()
+            .light_line_style(BACKGROUND.mix(0.0))
+            .bold_line_style(FOREGROUND.mix(0.1))
+            .axis_style(FOREGROUND.mix(0.5))
+            .label_style(text.clone())
+            .x_labels(8)
+            .x_label_formatter(&|t| ago(now - t))
+            .draw()?;
+
+        for change in map_changes(samples) {
+            chart.draw_series(LineSeries::new(
+                [(change.time, 0), (change.time, max)],
+                MAP_CHANGE.mix(0.6),
+            ))?;
+
+            chart.draw_series([Text::new(
+                change.map.clone(),
+                (change.time, max),
+                text.clone().color(&MAP_CHANGE),
+            )])?;
+        }
+
+        for (i, run) in runs(samples).into_iter().enumerate() {
+            let bots = chart.draw_series(LineSeries::new(
+                run.iter().map(|s| (s.time, s.bots)),
+                BOTS.stroke_width(2),
+            ))?;
+
+            // one legend entry per line, not per run
+            if i == 0 {
+                bots.label("Bots")
+                    .legend(|(x, y)| PathElement::new([(x, y), (x + 20, y)], BOTS));
+            }
+
+            let players = chart.draw_series(LineSeries::new(
+                run.iter().map(|s| (s.time, s.players)),
+                PLAYERS.stroke_width(2),
+            ))?;
+
+            if i == 0 {
+                players
+                    .label("Players")
+                    .legend(|(x, y)| PathElement::new([(x, y), (x + 20, y)], PLAYERS));
+            }
+        }
+
+        chart
+            .configure_series_labels()
+            .position(SeriesLabelPosition::UpperRight)
+            .background_style(BACKGROUND)
+            .border_style(FOREGROUND.mix(0.5))
+            .label_font(text)
+            .draw()?;
+
+        root.present()?;
+    }
+
+    let image =
+        image::RgbImage::from_raw(width, height, buf).ok_or("ChartError: Invalid chart buffer")?;
+
+    let mut png = Cursor::new(vec![]);
+    image.write_to(&mut png, image::ImageOutputFormat::Png)?;
+
+    Ok(png.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(time: i64, players: i64, map: &str) -> Sample {
+        Sample {
+            time,
+            players,
+            bots: 0,
+            max_players: 16,
+            map: map.into(),
+        }
+    }
+
+    fn samples() -> Vec<Sample> {
+        vec![
+            sample(0, 1, "dz_sirocco"),
+            sample(60, 2, "dz_sirocco"),
+            sample(120, 3, "dz_blacksite"),
+            // server was down for an hour
+            sample(3720, 0, "dz_blacksite"),
+            sample(3780, 1, "dz_sirocco"),
+        ]
+    }
+
+    #[test]
+    fn map_changes() {
+        let samples = samples();
+        let changes: Vec<_> = super::map_changes(&samples)
+            .iter()
+            .map(|s| (s.time, s.map.as_str()))
+            .collect();
+
+        assert_eq!(
+            changes,
+            [
+                (0, "dz_sirocco"),
+                (120, "dz_blacksite"),
+                (3780, "dz_sirocco")
+            ]
+        );
+    }
+
+    #[test]
+    fn gaps() {
+        let samples = samples();
+        let runs: Vec<_> = runs(&samples).iter().map(|r| r.len()).collect();
+
+        assert_eq!(runs, [3, 2]);
+    }
+
+    #[test]
+    fn png() {
+        let png = render_chart("test", &samples(), -60, 3840).unwrap();
+        assert!(png.starts_with(b"\x89PNG"));
+
+        // nothing recorded yet
+        let png = render_chart("test", &[], 0, 3600).unwrap();
+        assert!(png.starts_with(b"\x89PNG"));
+    }
+}
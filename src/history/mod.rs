@@ -0,0 +1,316 @@
+mod chart;
+
+use crate::db::DbConnection;
+use crate::poller::Poller;
+use crate::poller::Pollers;
+use crate::server_info::Info;
+use crate::servers::Servers;
+use crate::time::now;
+use crate::{Context, Error};
+use ::serenity::all::CreateAttachment;
+use chart::render_chart;
+use poise::serenity_prelude as serenity;
+use poise::CreateReply;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+use tokio::time;
+
+/// Seconds between two samples of a server
+pub const SAMPLE_INTERVAL: i64 = 60;
+/// How often samples older than the longest range are deleted
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+async fn record_samples(ctx: &serenity::Context) -> Result<(), Error> {
+    // don't hold up writers while servers are polled, the latest snapshot is good enough
+    let snapshots: Vec<(String, Option<Info>)> = {
+        let data = ctx.data.read().await;
+
+        let pollers = data
+            .get::<Pollers>()
+            .ok_or("DataError: Unable to get pollers")?;
+        let servers = data
+            .get::<Servers>()
+            .ok_or("DataError: Unable to get servers")?;
+
+        servers
+            .keys()
+            .map(|name| (name.clone(), pollers.get(name).and_then(Poller::latest)))
+            .collect()
+    };
+
+    let time = now()?;
+    let mut samples = vec![];
+
+    for (name, info) in snapshots {
+        // downtime is left as a gap
+        if let Some(Info::ServerUp(up)) = info {
+            let info = up.server_info;
+
+            samples.push((
+                name,
+                db::Sample {
+                    time,
+                    players: up.players.real().0.len() as i64,
+                    bots: info.bots as i64,
+                    max_players: info.max_players as i64,
+                    map: info.map.to_string(),
+                },
+            ));
+        }
+    }
+
+    if samples.is_empty() {
+        return Ok(());
+    }
+
+    let mut data = ctx.data.write().await;
+    let conn = data
+        .get_mut::<DbConnection>()
+        .ok_or("DataError: Unable to get database connection")?;
+
+    for (name, sample) in samples {
+        db::insert_sample(conn, &name, &sample).await?;
+    }
+
+    Ok(())
+}
+
+/// Nothing charts further back than `Range::Month`, so older samples only take up space
+async fn prune_samples(ctx: &serenity::Context) -> Result<(), Error> {
+    let before = now()? - Range::Month.seconds();
+
+    let mut data = ctx.data.write().await;
+    let conn = data
+        .get_mut::<DbConnection>()
+        .ok_or("DataError: Unable to get database connection")?;
+
+    db::remove_samples_before(conn, before).await
+}
+
+pub async fn history_loop(ctx: Arc<serenity::Context>) {
+    let mut interval = time::interval(Duration::from_secs(SAMPLE_INTERVAL as u64));
+    let mut pruned: Option<Instant> = None;
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = record_samples(&ctx).await {
+            eprintln!("Error with population history {e:?}");
+        }
+
+        if pruned.is_none_or(|t| t.elapsed() >= PRUNE_INTERVAL) {
+            pruned = Some(Instant::now());
+
+            if let Err(e) = prune_samples(&ctx).await {
+                eprintln!("Unable to prune population history {e:?}");
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, poise::ChoiceParameter)]
+pub enum Range {
+    #[name = "Last hour"]
+    Hour,
+    #[name = "Last 24 hours"]
+    Day,
+    #[name = "Last 7 days"]
+    Week,
+    #[name = "Last 30 days"]
+    Month,
+}
+
+impl Range {
+    fn seconds(&self) -> i64 {
+        match self {
+            Range::Hour => 3600,
+            Range::Day => 86400,
+            Range::Week => 7 * 86400,
+            Range::Month => 30 * 86400,
+        }
+    }
+}
+
+fn history_help() -> String {
+    "Show a chart of how many players were on a server, with map changes marked.".into()
+}
+
+#[poise::command(slash_command, help_text_fn = "history_help")]
+pub async fn history(
+    ctx: Context<'_>,
+    #[description = "Server identifier"] name: String,
+    #[description = "How far back to look, defaults to the last 24 hours"] range: Option<Range>,
+) -> Result<(), Error> {
+    let range = range.unwrap_or(Range::Day);
+
+    ctx.defer().await?;
+
+    let now = now()?;
+    let since = now - range.seconds();
+
+    let samples = {
+        let mut data = ctx.serenity_context().data.write().await;
+
+        if !data
+            .get::<Servers>()
+            .ok_or("DataError: Unable to get servers")?
+            .contains_key(&name)
+        {
+            return Err(format!("Server {name} doesn't exist").into());
+        }
+
+        let conn = data
+            .get_mut::<DbConnection>()
+            .ok_or("DataError: Unable to get database connection")?;
+
+        db::read_samples(conn, &name, since).await?
+    };
+
+    let peak = samples.iter().map(|s| s.players).max().unwrap_or(0);
+    let average = if samples.is_empty() {
+        0.0
+    } else {
+        samples.iter().map(|s| s.players).sum::<i64>() as f64 / samples.len() as f64
+    };
+
+    // plotting is cpu bound, keep it off the async workers
+    let chart = {
+        let name = name.clone();
+        tokio::task::spawn_blocking(move || render_chart(&name, &samples, since, now)).await??
+    };
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!(
+                "`{}` since <t:{}:f>, peak {} players, average {:.1}",
+                name, since, peak, average
+            ))
+            .attachment(CreateAttachment::bytes(chart, format!("{name}.png"))),
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub mod db {
+    use crate::Error;
+    use sqlx::SqliteConnection;
+
+    pub struct Sample {
+        /// unix timestamp
+        pub time: i64,
+        /// humans only
+        pub players: i64,
+        pub bots: i64,
+        pub max_players: i64,
+        pub map: String,
+    }
+
+    pub async fn insert_sample(
+        conn: &mut SqliteConnection,
+        server: &str,
+        sample: &Sample,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            "INSERT INTO population_samples (server_name, time, players, bots, max_players, map) VALUES (?, ?, ?, ?, ?, ?)",
+            server,
+            sample.time,
+            sample.players,
+            sample.bots,
+            sample.max_players,
+            sample.map
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn read_samples(
+        conn: &mut SqliteConnection,
+        server: &str,
+        since: i64,
+    ) -> Result<Vec<Sample>, Error> {
+        let samples = sqlx::query_as!(
+            Sample,
+            "SELECT time, players, bots, max_players, map FROM population_samples WHERE server_name = ? AND time >= ? ORDER BY time",
+            server,
+            since
+        )
+        .fetch_all(conn)
+        .await?;
+
+        Ok(samples)
+    }
+
+    pub async fn remove_samples_before(
+        conn: &mut SqliteConnection,
+        before: i64,
+    ) -> Result<(), Error> {
+        sqlx::query!("DELETE FROM population_samples WHERE time < ?", before)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_server_samples(
+        conn: &mut SqliteConnection,
+        server: &str,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            "DELETE FROM population_samples WHERE server_name = ?",
+            server
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::db::*;
+    use sqlx::{Connection, SqliteConnection};
+
+    fn sample(time: i64) -> Sample {
+        Sample {
+            time,
+            players: 1,
+            bots: 0,
+            max_players: 16,
+            map: "dz_sirocco".into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn removes_samples() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&mut conn).await.unwrap();
+
+        for time in [100, 200, 300] {
+            insert_sample(&mut conn, "meow", &sample(time))
+                .await
+                .unwrap();
+            insert_sample(&mut conn, "woof", &sample(time))
+                .await
+                .unwrap();
+        }
+
+        remove_samples_before(&mut conn, 200).await.unwrap();
+        let times = |samples: Vec<Sample>| samples.iter().map(|s| s.time).collect::<Vec<_>>();
+        assert_eq!(
+            times(read_samples(&mut conn, "meow", 0).await.unwrap()),
+            [200, 300]
+        );
+
+        remove_server_samples(&mut conn, "meow").await.unwrap();
+        assert!(read_samples(&mut conn, "meow", 0).await.unwrap().is_empty());
+        assert_eq!(
+            times(read_samples(&mut conn, "woof", 0).await.unwrap()),
+            [200, 300]
+        );
+    }
+}
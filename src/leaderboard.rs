@@ -0,0 +1,278 @@
+use crate::db::DbConnection;
+use crate::servers::Servers;
+use crate::time::format_duration;
+use crate::time::now;
+use crate::{Context, Error};
+use poise::CreateReply;
+use serde::Deserialize;
+use std::fmt::Write;
+
+#[derive(Debug, Default, Clone, Copy, Deserialize, poise::ChoiceParameter)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    #[name = "Last 24 hours"]
+    Day,
+    #[default]
+    #[name = "Last 7 days"]
+    Week,
+    #[name = "Last 30 days"]
+    Month,
+    #[name = "All time"]
+    All,
+}
+
+impl Period {
+    /// Unix timestamp of the start of this period
+    pub fn since(&self, now: i64) -> i64 {
+        match self {
+            Period::Day => now - 86400,
+            Period::Week => now - 7 * 86400,
+            Period::Month => now - 30 * 86400,
+            Period::All => 0,
+        }
+    }
+}
+
+fn leaderboard_help() -> String {
+    "Show the players with the most wins and playtime on a server. \
+A win is having the top score when the map changes."
+        .into()
+}
+
+#[poise::command(slash_command, help_text_fn = "leaderboard_help")]
+pub async fn leaderboard(
+    ctx: Context<'_>,
+    #[description = "Server identifier"] name: String,
+    #[description = "Defaults to the last 7 days"] period: Option<Period>,
+) -> Result<(), Error> {
+    let period = period.unwrap_or_default();
+    let now = now()?;
+
+    let entries = {
+        let mut data = ctx.serenity_context().data.write().await;
+
+        if !data
+            .get::<Servers>()
+            .ok_or("DataError: Unable to get servers")?
+            .contains_key(&name)
+        {
+            return Err(format!("Server {name} doesn't exist").into());
+        }
+
+        let conn = data
+            .get_mut::<DbConnection>()
+            .ok_or("DataError: Unable to get database connection")?;
+
+        db::read_leaderboard(conn, &name, period.since(now), now, 15).await?
+    };
+
+    if entries.is_empty() {
+        ctx.send(
+            CreateReply::default()
+                .content(format!("Nobody has played on `{name}` yet"))
+                .ephemeral(true),
+        )
+        .await?;
+
+        return Ok(());
+    }
+
+    let width = entries
+        .iter()
+        .map(|e| e.player_name.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max(6);
+
+    let mut table = format!(
+        "{:>2}  {:<width$}  {:>4}  {:>4}  {:>8}\n",
+        "#", "Player", "Wins", "Best", "Playtime"
+    );
+
+    for (i, e) in entries.iter().enumerate() {
+        _ = writeln!(
+            table,
+            "{:>2}  {:<width$}  {:>4}  {:>4}  {:>8}",
+            i + 1,
+            e.player_name,
+            e.wins,
+            e.best_score,
+            format_duration(e.seconds),
+        );
+    }
+
+    ctx.send(CreateReply::default().content(format!(
+        "Leaderboard of `{}`, {}\n```\n{}```",
+        name,
+        poise::ChoiceParameter::name(&period).to_lowercase(),
+        table
+    )))
+    .await?;
+
+    Ok(())
+}
+
+pub mod db {
+    use crate::Error;
+    use serde::Serialize;
+    use sqlx::SqliteConnection;
+
+    #[derive(Debug, Serialize)]
+    pub struct LeaderboardEntry {
+        pub player_name: String,
+        pub wins: i64,
+        pub best_score: i64,
+        /// Total playtime in seconds
+        pub seconds: i64,
+    }
+
+    /// Players ranked by wins, then playtime, between `since` and `now`
+    pub async fn read_leaderboard(
+        conn: &mut SqliteConnection,
+        server: &str,
+        since: i64,
+        now: i64,
+        limit: i64,
+    ) -> Result<Vec<LeaderboardEntry>, Error> {
+        let entries = sqlx::query_as!(
+            LeaderboardEntry,
+            r#"WITH play AS (
+    SELECT player_name,
+           SUM(COALESCE(leave_time, ?) - MAX(join_time, ?)) AS seconds,
+           MAX(peak_score) AS best_score
+    FROM player_sessions
+    WHERE server_name = ? AND COALESCE(leave_time, ?) >= ?
+    GROUP BY player_name
+), wins AS (
+    SELECT winner, COUNT(*) AS wins
+    FROM map_rotations
+    WHERE server_name = ? AND winner IS NOT NULL AND end_time >= ?
+    GROUP BY winner
+)
+SELECT play.player_name AS "player_name!: String",
+       COALESCE(wins.wins, 0) AS "wins!: i64",
+       play.best_score AS "best_score!: i64",
+       play.seconds AS "seconds!: i64"
+FROM play LEFT JOIN wins ON wins.winner = play.player_name
+ORDER BY 2 DESC, 4 DESC
+LIMIT ?"#,
+            now,
+            since,
+            server,
+            now,
+            since,
+            server,
+            since,
+            limit
+        )
+        .fetch_all(conn)
+        .await?;
+
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::db::read_leaderboard;
+    use super::*;
+    use sqlx::{Connection, SqliteConnection};
+
+    const NOW: i64 = 100 * 86400;
+
+    async fn database() -> SqliteConnection {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&mut conn).await.unwrap();
+
+        conn
+    }
+
+    async fn session(
+        conn: &mut SqliteConnection,
+        server: &str,
+        player: &str,
+        join: i64,
+        leave: Option<i64>,
+        peak: i64,
+    ) {
+        sqlx::query(
+            "INSERT INTO player_sessions (server_name, player_name, join_time, leave_time, last_seen, peak_score)
+VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(server)
+        .bind(player)
+        .bind(join)
+        .bind(leave)
+        .bind(leave.unwrap_or(NOW))
+        .bind(peak)
+        .execute(conn)
+        .await
+        .unwrap();
+    }
+
+    async fn win(conn: &mut SqliteConnection, server: &str, winner: &str, end: i64) {
+        sqlx::query(
+            "INSERT INTO map_rotations (server_name, map, start_time, end_time, last_seen, winner)
+VALUES (?, 'dz_sirocco', ?, ?, ?, ?)",
+        )
+        .bind(server)
+        .bind(end - 1800)
+        .bind(end)
+        .bind(end)
+        .bind(winner)
+        .execute(conn)
+        .await
+        .unwrap();
+    }
+
+    async fn read(conn: &mut SqliteConnection, period: Period) -> Vec<(String, i64, i64, i64)> {
+        read_leaderboard(conn, "meow", period.since(NOW), NOW, 15)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.player_name, e.wins, e.best_score, e.seconds))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn ranking() {
+        let mut conn = database().await;
+        let conn = &mut conn;
+
+        session(conn, "meow", "cat", NOW - 3600, Some(NOW - 1800), 10).await;
+        win(conn, "meow", "cat", NOW - 1800).await;
+        win(conn, "meow", "cat", NOW - 600).await;
+
+        // still on, since two days ago
+        session(conn, "meow", "dog", NOW - 2 * 86400, None, 20).await;
+
+        session(
+            conn,
+            "meow",
+            "bird",
+            NOW - 3 * 86400,
+            Some(NOW - 3 * 86400 + 3600),
+            30,
+        )
+        .await;
+        win(conn, "meow", "bird", NOW - 3 * 86400 + 3600).await;
+
+        // other servers don't count
+        session(conn, "woof", "cat", NOW - 3600, Some(NOW), 50).await;
+        win(conn, "woof", "dog", NOW - 600).await;
+
+        assert_eq!(
+            read(conn, Period::Day).await,
+            [("cat".into(), 2, 10, 1800), ("dog".into(), 0, 20, 86400),]
+        );
+
+        assert_eq!(
+            read(conn, Period::Week).await,
+            [
+                ("cat".into(), 2, 10, 1800),
+                ("bird".into(), 1, 30, 3600),
+                ("dog".into(), 0, 20, 2 * 86400),
+            ]
+        );
+    }
+}
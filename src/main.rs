@@ -1,23 +1,55 @@
+use crate::alerts::alerts;
+use crate::alerts::db::read_alerts;
+use crate::alerts::Alerts;
+use crate::discover::discover;
+use crate::rcon::changelevel;
+use crate::rcon::kick;
+use crate::rcon::rcon;
+use crate::rcon::set_rcon_password;
 use crate::servers::create_server;
 use crate::servers::delete_server;
 use crate::servers::Servers;
-use crate::socket::ServerSocket;
 use crate::status::activity::bot_status_loop;
 use crate::status::status;
 use crate::webserver::server;
 use db::DbConnection;
 use down_detector::down_detector_loop;
+use down_detector::set_down_detection;
+use gsi::db::read_feeds;
+use gsi::db::read_snapshots;
+use gsi::db::read_tokens;
+use gsi::feed::feed_loop;
+use gsi::feed::Feeds;
+use gsi::gsi;
+use gsi::GameStateIntegrationTokens;
+use gsi::GameStates;
+use history::history;
+use history::history_loop;
+use leaderboard::leaderboard;
+use maps::db::close_dangling_rotations;
+use maps::map_tracker_loop;
+use maps::maps;
+use notify::db::read_subscriptions;
+use notify::notify;
+use notify::notify_loop;
+use notify::unnotify;
+use notify::Subscriptions;
 use once_cell::sync::Lazy;
 use poise::samples::on_error;
 use poise::serenity_prelude as serenity;
 use poise::CreateReply;
+use poller::start_poller;
+use poller::Pollers;
+use poller::PollersValue;
 use servers::db::read_servers;
 use servers::list_servers;
 use servers::Server;
+use sessions::db::close_dangling_sessions;
+use sessions::session_tracker_loop;
+use sessions::sessions;
 use settings::db::read_settings;
 use settings::set_external_redirector;
-use socket::update_socket;
-use socket::ServerSocketValue;
+use settings::set_master_server;
 use sqlx::Connection;
 use sqlx::SqliteConnection;
 use status::updating::create_updating_status;
@@ -33,16 +65,38 @@ use tokio::sync::RwLock;
 use tokio::task::JoinHandle;
 use tracing::Level;
 use tracing_subscriber::FmtSubscriber;
+use watch::db::read_watches;
+use watch::unwatch;
+use watch::watch;
+use watch::watch_loop;
+use watch::Watches;
+use webhooks::db::read_webhooks;
+use webhooks::webhooks;
+use webhooks::Webhooks;
 
 use std::env;
 
+mod alerts;
 mod db;
+mod discover;
 mod down_detector;
+mod gsi;
+mod history;
+mod leaderboard;
+mod maps;
+mod notify;
+mod poller;
+mod rcon;
 mod server_info;
 mod servers;
+mod sessions;
 mod settings;
 mod socket;
 mod status;
+mod time;
+mod tracker;
+mod watch;
+mod webhooks;
 mod webserver;
 // mod queue;
 
@@ -112,6 +166,10 @@ async fn event_handler(
                 tokio::spawn(bot_status_loop(Arc::new(ctx.clone()))),
                 tokio::spawn(status_message_update_loop(Arc::new(ctx.clone()))),
                 tokio::spawn(down_detector_loop(Arc::new(ctx.clone()))),
+                tokio::spawn(history_loop(Arc::new(ctx.clone()))),
+                tokio::spawn(notify_loop(Arc::new(ctx.clone()))),
+                tokio::spawn(watch_loop(Arc::new(ctx.clone()))),
+                tokio::spawn(feed_loop(Arc::new(ctx.clone()))),
             ];
 
             let mut t = TASKS.write().await;
@@ -130,6 +188,10 @@ async fn event_handler(
                 tokio::spawn(bot_status_loop(Arc::new(ctx.clone()))),
                 tokio::spawn(status_message_update_loop(Arc::new(ctx.clone()))),
                 tokio::spawn(down_detector_loop(Arc::new(ctx.clone()))),
+                tokio::spawn(history_loop(Arc::new(ctx.clone()))),
+                tokio::spawn(notify_loop(Arc::new(ctx.clone()))),
+                tokio::spawn(watch_loop(Arc::new(ctx.clone()))),
+                tokio::spawn(feed_loop(Arc::new(ctx.clone()))),
             ];
 
             t.clear();
@@ -179,8 +241,26 @@ async fn main() -> Result<(), Error> {
                 delete_server(),
                 list_servers(),
                 set_external_redirector(),
+                set_master_server(),
                 create_updating_status(),
                 delete_updating_status(),
+                rcon(),
+                changelevel(),
+                kick(),
+                set_rcon_password(),
+                discover(),
+                sessions(),
+                history(),
+                maps(),
+                leaderboard(),
+                set_down_detection(),
+                alerts(),
+                webhooks(),
+                notify(),
+                unnotify(),
+                watch(),
+                unwatch(),
+                gsi(),
             ],
             prefix_options: poise::PrefixFrameworkOptions {
                 prefix: Some("!".into()),
@@ -195,6 +275,11 @@ async fn main() -> Result<(), Error> {
                 // Webserver
                 tokio::spawn(server(Arc::new(ctx.clone())));
 
+                // Trackers keep open rows between polls, restarting them on every Resume
+                // would close and reopen all of them
+                tokio::spawn(session_tracker_loop(Arc::new(ctx.clone())));
+                tokio::spawn(map_tracker_loop(Arc::new(ctx.clone())));
+
                 Ok(UserData {})
             })
         })
@@ -210,20 +295,31 @@ async fn main() -> Result<(), Error> {
 
         sqlx::migrate!().run(&mut conn).await?;
 
+        // left open by the last run, the trackers start over
+        close_dangling_sessions(&mut conn).await?;
+        close_dangling_rotations(&mut conn).await?;
+
         let mut servers: HashMap<String, Server> = HashMap::new();
         read_servers(&mut servers, &mut conn).await?;
 
-        let mut sockets: ServerSocketValue = HashMap::new();
+        let mut pollers: PollersValue = HashMap::new();
 
-	for (n, a) in &servers {
-	    update_socket(&mut sockets, n.clone(), &a.addr).await?;
+	for server in servers.values() {
+	    start_poller(&mut pollers, server).await?;
 	}
 
         read_settings(&mut data, &mut conn).await?;
         data.insert::<UpdatingStatusMessages>(
 	    read_updating_status_messages(&mut conn).await?
 	);
-        data.insert::<ServerSocket>(sockets);
+        data.insert::<Pollers>(pollers);
+        data.insert::<Alerts>(read_alerts(&mut conn).await?);
+        data.insert::<Webhooks>(read_webhooks(&mut conn).await?);
+        data.insert::<Subscriptions>(read_subscriptions(&mut conn).await?);
+        data.insert::<Watches>(read_watches(&mut conn).await?);
+        data.insert::<GameStateIntegrationTokens>(read_tokens(&mut conn).await?);
+        data.insert::<GameStates>(read_snapshots(&mut conn).await?);
+        data.insert::<Feeds>(read_feeds(&mut conn).await?);
         data.insert::<Servers>(servers);
         data.insert::<DbConnection>(conn);
     }
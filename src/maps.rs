@@ -0,0 +1,479 @@
+use crate::db::DbConnection;
+use crate::server_info::PlayerDiff;
+use crate::servers::Servers;
+use crate::time::format_duration;
+use crate::tracker::tracker_loop;
+use crate::tracker::Tracker;
+use crate::tracker::FLUSH_INTERVAL;
+use crate::{Context, Error};
+use csgo_server::players::Players;
+use poise::serenity_prelude as serenity;
+use poise::CreateReply;
+use sqlx::SqliteConnection;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+
+/// The map a server is currently running
+struct Rotation {
+    id: i64,
+    map: Box<str>,
+    /// Time and player count of the previous poll
+    last_time: i64,
+    players: i64,
+    peak: i64,
+    player_seconds: i64,
+    flushed: i64,
+    /// Top scorer of the previous poll
+    leader: Option<Box<str>>,
+}
+
+impl Rotation {
+    fn advance(&mut self, time: i64, players: i64) {
+        self.player_seconds += self.players * (time - self.last_time).max(0);
+        self.last_time = time;
+        self.players = players;
+        self.peak = self.peak.max(players);
+    }
+}
+
+enum Op {
+    Open {
+        map: Box<str>,
+        players: i64,
+        /// The map was already running when the tracker or the server came up
+        partial: bool,
+    },
+    Update(db::Stats),
+    /// `None` winner and `true` if the server went down instead of changing maps
+    Close(db::Stats, Option<Box<str>>, bool),
+}
+
+fn top_scorer(players: &Players) -> Option<Box<str>> {
+    players
+        .0
+        .iter()
+        .filter(|p| p.score > 0)
+        .max_by_key(|p| p.score)
+        .map(|p| p.name.clone())
+}
+
+/// The current rotation of every server
+#[derive(Default)]
+struct Rotations(HashMap<String, Rotation>);
+
+impl Rotations {
+    fn opened(&mut self, diff: &PlayerDiff, id: i64, map: Box<str>, players: i64, time: i64) {
+        self.0.insert(
+            diff.server.clone(),
+            Rotation {
+                id,
+                map,
+                last_time: time,
+                players,
+                peak: players,
+                player_seconds: 0,
+                flushed: time,
+                leader: top_scorer(&diff.players),
+            },
+        );
+    }
+}
+
+impl Tracker for Rotations {
+    type Op = Op;
+    const NAME: &'static str = "map tracker";
+
+    fn diff_ops(&mut self, diff: &PlayerDiff, time: i64) -> Vec<Op> {
+        let rotations = &mut self.0;
+        let players = diff.players.0.len() as i64;
+        let mut ops = vec![];
+
+        // nothing to compare against, we don't know when this map started
+        let mut partial = true;
+
+        if let Some(r) = rotations.get_mut(&diff.server) {
+            r.advance(time, players);
+
+            if diff.map.as_ref() == Some(&r.map) {
+                r.leader = top_scorer(&diff.players);
+
+                if time - r.flushed >= FLUSH_INTERVAL {
+                    r.flushed = time;
+                    ops.push(Op::Update(db::Stats::new(r)));
+                }
+
+                return ops;
+            }
+
+            // map changed or the server went down, only a finished map has a winner
+            if let Some(r) = rotations.remove(&diff.server) {
+                let winner = diff.map.as_ref().and(r.leader.clone());
+                ops.push(Op::Close(db::Stats::new(&r), winner, diff.map.is_none()));
+            }
+
+            partial = false;
+        }
+
+        if let Some(map) = &diff.map {
+            ops.push(Op::Open {
+                map: map.clone(),
+                players,
+                partial,
+            });
+        }
+
+        ops
+    }
+
+    async fn apply(
+        &mut self,
+        conn: &mut SqliteConnection,
+        diff: &PlayerDiff,
+        time: i64,
+        op: Op,
+    ) -> Result<(), Error> {
+        match op {
+            Op::Open {
+                map,
+                players,
+                partial,
+            } => {
+                let id =
+                    db::open_rotation(conn, &diff.server, &map, time, players, partial).await?;
+                self.opened(diff, id, map, players, time);
+            }
+            Op::Update(stats) => db::update_rotation(conn, &stats).await?,
+            Op::Close(stats, winner, interrupted) => {
+                db::close_rotation(conn, &stats, winner.as_deref(), interrupted).await?
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub async fn map_tracker_loop(ctx: Arc<serenity::Context>) {
+    tracker_loop(ctx, Rotations::default()).await
+}
+
+fn maps_help() -> String {
+    "Show the most played maps of a server, how long matches last and how busy each map gets.
+Matches cut short by downtime don't count towards their average length."
+        .into()
+}
+
+#[poise::command(slash_command, help_text_fn = "maps_help")]
+pub async fn maps(
+    ctx: Context<'_>,
+    #[description = "Server identifier"] name: String,
+) -> Result<(), Error> {
+    let stats = {
+        let mut data = ctx.serenity_context().data.write().await;
+
+        if !data
+            .get::<Servers>()
+            .ok_or("DataError: Unable to get servers")?
+            .contains_key(&name)
+        {
+            return Err(format!("Server {name} doesn't exist").into());
+        }
+
+        let conn = data
+            .get_mut::<DbConnection>()
+            .ok_or("DataError: Unable to get database connection")?;
+
+        db::read_map_stats(conn, &name).await?
+    };
+
+    if stats.is_empty() {
+        ctx.send(
+            CreateReply::default()
+                .content(format!("No maps recorded for `{name}` yet"))
+                .ephemeral(true),
+        )
+        .await?;
+
+        return Ok(());
+    }
+
+    let width = stats.iter().map(|s| s.map.len()).max().unwrap_or(0).max(3);
+
+    let mut table = format!(
+        "{:<width$}  {:>6}  {:>8}  {:>9}  {:>11}  {:>4}\n",
+        "Map", "Played", "Time", "Avg match", "Avg players", "Peak"
+    );
+
+    for s in &stats {
+        let average_players = if s.seconds > 0 {
+            s.player_seconds as f64 / s.seconds as f64
+        } else {
+            0.0
+        };
+
+        _ = writeln!(
+            table,
+            "{:<width$}  {:>6}  {:>8}  {:>9}  {:>11.1}  {:>4}",
+            s.map,
+            s.plays,
+            format_duration(s.seconds),
+            s.average_length
+                .map(|l| format_duration(l as i64))
+                .unwrap_or("-".into()),
+            average_players,
+            s.peak,
+        );
+    }
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!("Maps played on `{name}`\n```\n{table}```"))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub mod db {
+    use super::Rotation;
+    use crate::Error;
+    use sqlx::SqliteConnection;
+
+    /// Running statistics of a rotation at some point in time
+    pub struct Stats {
+        pub(super) id: i64,
+        time: i64,
+        pub(super) peak: i64,
+        pub(super) player_seconds: i64,
+    }
+
+    impl Stats {
+        pub(super) fn new(r: &Rotation) -> Self {
+            Stats {
+                id: r.id,
+                time: r.last_time,
+                peak: r.peak,
+                player_seconds: r.player_seconds,
+            }
+        }
+    }
+
+    /// A `partial` rotation on the map the server's last rotation was interrupted on continues it
+    pub async fn open_rotation(
+        conn: &mut SqliteConnection,
+        server: &str,
+        map: &str,
+        time: i64,
+        players: i64,
+        partial: bool,
+    ) -> Result<i64, Error> {
+        let res = sqlx::query!(
+            "INSERT INTO map_rotations (server_name, map, start_time, last_seen, peak_players, partial, continued)
+VALUES (?1, ?2, ?3, ?3, ?4, ?5, ?5 AND COALESCE((
+    SELECT partial AND map = ?2 FROM map_rotations WHERE server_name = ?1 ORDER BY id DESC LIMIT 1
+), FALSE))",
+            server,
+            map,
+            time,
+            players,
+            partial
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(res.last_insert_rowid())
+    }
+
+    pub async fn update_rotation(conn: &mut SqliteConnection, stats: &Stats) -> Result<(), Error> {
+        sqlx::query!(
+            "UPDATE map_rotations SET last_seen = ?, peak_players = ?, player_seconds = ? WHERE id = ?",
+            stats.time,
+            stats.peak,
+            stats.player_seconds,
+            stats.id
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// `interrupted` if the server went down instead of changing maps
+    pub async fn close_rotation(
+        conn: &mut SqliteConnection,
+        stats: &Stats,
+        winner: Option<&str>,
+        interrupted: bool,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            "UPDATE map_rotations SET end_time = ?, last_seen = ?, peak_players = ?, player_seconds = ?, winner = ?, partial = partial OR ? WHERE id = ?",
+            stats.time,
+            stats.time,
+            stats.peak,
+            stats.player_seconds,
+            winner,
+            interrupted,
+            stats.id
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Rotations left open by a restart end when the server was last seen on that map
+    pub async fn close_dangling_rotations(conn: &mut SqliteConnection) -> Result<(), Error> {
+        sqlx::query!(
+            "UPDATE map_rotations SET end_time = last_seen, partial = TRUE WHERE end_time IS NULL"
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub struct MapStats {
+        pub map: String,
+        /// Rotations continuing one cut short by downtime don't count again
+        pub plays: i64,
+        /// Total time on this map, including the current rotation
+        pub seconds: i64,
+        /// Average length of rotations that were seen from start to end
+        pub average_length: Option<f64>,
+        pub peak: i64,
+        pub player_seconds: i64,
+    }
+
+    pub async fn read_map_stats(
+        conn: &mut SqliteConnection,
+        server: &str,
+    ) -> Result<Vec<MapStats>, Error> {
+        let stats = sqlx::query_as!(
+            MapStats,
+            r#"SELECT map,
+       SUM(NOT continued) AS "plays!: i64",
+       SUM(COALESCE(end_time, last_seen) - start_time) AS "seconds!: i64",
+       AVG(CASE WHEN NOT partial THEN end_time - start_time END) AS "average_length: f64",
+       MAX(peak_players) AS "peak!: i64",
+       SUM(player_seconds) AS "player_seconds!: i64"
+FROM map_rotations
+WHERE server_name = ?
+GROUP BY map
+ORDER BY 3 DESC
+LIMIT 15"#,
+            server
+        )
+        .fetch_all(conn)
+        .await?;
+
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csgo_server::testing::player;
+    use std::time::SystemTime;
+
+    fn diff(map: Option<&str>, players: &[(&str, i32)]) -> PlayerDiff {
+        PlayerDiff {
+            server: "meow".into(),
+            time: SystemTime::now(),
+            joined: vec![],
+            left: vec![],
+            players: Players(
+                players
+                    .iter()
+                    .map(|(n, score)| player(0, n, *score, 0.0))
+                    .collect(),
+            ),
+            map: map.map(Box::from),
+        }
+    }
+
+    /// Apply `ops` the way `apply` does without a database, the open time is the id
+    fn poll(rotations: &mut Rotations, diff: PlayerDiff, time: i64) -> Vec<String> {
+        let ops = rotations.diff_ops(&diff, time);
+
+        ops.into_iter()
+            .map(|op| match op {
+                Op::Open {
+                    map,
+                    players,
+                    partial,
+                } => {
+                    let line = format!("open {map} {players} {partial}");
+                    rotations.opened(&diff, time, map, players, time);
+                    line
+                }
+                Op::Update(s) => format!("update {} {} {}", s.id, s.peak, s.player_seconds),
+                Op::Close(s, winner, interrupted) => format!(
+                    "close {} {} {:?} {}",
+                    s.id,
+                    s.player_seconds,
+                    winner.as_deref(),
+                    interrupted
+                ),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn map_change() {
+        let mut rotations = Rotations::default();
+
+        // already running when the tracker started
+        let ops = poll(&mut rotations, diff(Some("dz_sirocco"), &[("cat", 0)]), 0);
+        assert_eq!(ops, ["open dz_sirocco 1 true"]);
+
+        let ops = poll(
+            &mut rotations,
+            diff(Some("dz_sirocco"), &[("cat", 2), ("dog", 5)]),
+            10,
+        );
+        assert!(ops.is_empty());
+
+        let ops = poll(&mut rotations, diff(Some("dz_county"), &[("cat", 0)]), 20);
+        assert_eq!(
+            ops,
+            ["close 0 30 Some(\"dog\") false", "open dz_county 1 false"]
+        );
+    }
+
+    #[test]
+    fn server_down() {
+        let mut rotations = Rotations::default();
+
+        poll(&mut rotations, diff(Some("dz_sirocco"), &[("cat", 3)]), 0);
+
+        let ops = poll(&mut rotations, diff(None, &[]), 10);
+        assert_eq!(ops, ["close 0 10 None true"]);
+
+        // whatever it's on now was already running when it came back
+        let ops = poll(&mut rotations, diff(Some("dz_sirocco"), &[]), 20);
+        assert_eq!(ops, ["open dz_sirocco 0 true"]);
+    }
+
+    #[test]
+    fn flush_throttling() {
+        let mut rotations = Rotations::default();
+
+        poll(&mut rotations, diff(Some("dz_sirocco"), &[("cat", 0)]), 0);
+
+        let ops = poll(
+            &mut rotations,
+            diff(Some("dz_sirocco"), &[("cat", 0), ("dog", 0)]),
+            FLUSH_INTERVAL - 1,
+        );
+        assert!(ops.is_empty());
+
+        let ops = poll(
+            &mut rotations,
+            diff(Some("dz_sirocco"), &[]),
+            FLUSH_INTERVAL,
+        );
+        assert_eq!(ops, [format!("update 0 2 {}", FLUSH_INTERVAL + 1)]);
+    }
+}
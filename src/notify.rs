@@ -0,0 +1,435 @@
+use crate::db::DbConnection;
+use crate::server_info::PlayerDiff;
+use crate::server_info::PLAYER_DIFFS;
+use crate::servers::Servers;
+use crate::time::unix;
+use crate::{Context, Error};
+use ::serenity::all::CreateMessage;
+use poise::serenity_prelude as serenity;
+use poise::serenity_prelude::prelude::TypeMapKey;
+use poise::CreateReply;
+use serenity::{ChannelId, UserId};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Someone who wants to know when a server gets busy
+#[derive(Debug, Clone)]
+pub struct Subscription {
+    pub user: UserId,
+    pub server: String,
+    pub min_players: i64,
+    /// Mention the user here instead of sending a DM
+    pub channel: Option<ChannelId>,
+    /// Seconds between notifications
+    pub cooldown: i64,
+    pub last_notified: Option<i64>,
+}
+
+impl Subscription {
+    /// Whether going from `before` to `after` players crosses the threshold outside the cooldown
+    fn due(&self, before: usize, after: usize, now: i64) -> bool {
+        let min = self.min_players.max(1) as usize;
+
+        before < min
+            && after >= min
+            && self
+                .last_notified
+                .is_none_or(|last| now - last >= self.cooldown)
+    }
+}
+
+pub struct Subscriptions;
+pub type SubscriptionsValue = Vec<Subscription>;
+impl TypeMapKey for Subscriptions {
+    type Value = SubscriptionsValue;
+}
+
+async fn send_notification(
+    ctx: &serenity::Context,
+    sub: &Subscription,
+    players: usize,
+    map: Option<&str>,
+) -> Result<(), Error> {
+    let text = format!(
+        "`{}` has {} players online{}",
+        sub.server,
+        players,
+        map.map(|m| format!(" on {m}")).unwrap_or_default()
+    );
+
+    match sub.channel {
+        Some(channel) => {
+            let message = CreateMessage::new().content(format!("<@{}> {}", sub.user, text));
+            channel.send_message(ctx, message).await?;
+        }
+        None => {
+            let message = CreateMessage::new().content(text);
+            sub.user.direct_message(ctx, message).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// `counts` holds the player count of every server after its previous poll
+async fn check_thresholds(
+    ctx: &serenity::Context,
+    counts: &mut HashMap<String, usize>,
+    diff: PlayerDiff,
+) -> Result<(), Error> {
+    let after = diff.players.0.len();
+
+    // the first poll is only a baseline, whoever is on now didn't just cross anything
+    let Some(before) = counts.insert(diff.server.clone(), after) else {
+        return Ok(());
+    };
+
+    if after <= before {
+        return Ok(());
+    }
+
+    let now = unix(diff.time)?;
+
+    let due: Vec<Subscription> = {
+        let data = ctx.data.read().await;
+
+        data.get::<Subscriptions>()
+            .ok_or("DataError: Unable to get subscriptions")?
+            .iter()
+            .filter(|s| s.server == diff.server && s.due(before, after, now))
+            .cloned()
+            .collect()
+    };
+
+    if due.is_empty() {
+        return Ok(());
+    }
+
+    for sub in &due {
+        // closed DMs or a deleted channel shouldn't stop everyone else's notifications
+        if let Err(e) = send_notification(ctx, sub, after, diff.map.as_deref()).await {
+            eprintln!("Unable to notify {} about {}: {e}", sub.user, sub.server);
+        }
+    }
+
+    let mut data = ctx.data.write().await;
+
+    for sub in data
+        .get_mut::<Subscriptions>()
+        .ok_or("DataError: Unable to get subscriptions")?
+        .iter_mut()
+        .filter(|s| s.server == diff.server && due.iter().any(|d| d.user == s.user))
+    {
+        sub.last_notified = Some(now);
+    }
+
+    let conn = data
+        .get_mut::<DbConnection>()
+        .ok_or("DataError: Unable to get database connection")?;
+
+    for sub in &due {
+        db::set_last_notified(conn, sub.user, &sub.server, now).await?;
+    }
+
+    Ok(())
+}
+
+pub async fn notify_loop(ctx: Arc<serenity::Context>) {
+    let mut diffs = PLAYER_DIFFS.subscribe();
+    let mut counts = HashMap::new();
+
+    loop {
+        match diffs.recv().await {
+            Ok(diff) => {
+                if let Err(e) = check_thresholds(&ctx, &mut counts, diff).await {
+                    eprintln!("Error with player notifications {e:?}");
+                }
+            }
+            Err(RecvError::Lagged(n)) => eprintln!("Player notifications missed {n} polls"),
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+fn notify_help() -> String {
+    "Get a DM, or a mention in this channel, when a server reaches some number of players.
+Only real players count, bots and GOTV don't. Subscribing again replaces your old subscription."
+        .into()
+}
+
+#[poise::command(
+    slash_command,
+    help_text_fn = "notify_help",
+    category = "Notifications"
+)]
+pub async fn notify(
+    ctx: Context<'_>,
+    #[description = "Server identifier"] name: String,
+    #[description = "Notify when at least this many players are on"]
+    #[min = 1]
+    min_players: u8,
+    #[description = "Mention me in this channel instead of sending a DM"] here: Option<bool>,
+    #[description = "Minutes between notifications, defaults to 60"] cooldown: Option<u32>,
+) -> Result<(), Error> {
+    let mut data = ctx.serenity_context().data.write().await;
+
+    if !data
+        .get::<Servers>()
+        .ok_or("DataError: Unable to get servers")?
+        .contains_key(&name)
+    {
+        return Err(format!("Server {name} doesn't exist").into());
+    }
+
+    let sub = Subscription {
+        user: ctx.author().id,
+        server: name,
+        min_players: min_players.max(1) as i64,
+        channel: here.unwrap_or(false).then(|| ctx.channel_id()),
+        cooldown: cooldown.unwrap_or(60) as i64 * 60,
+        last_notified: None,
+    };
+
+    let subs = data
+        .get_mut::<Subscriptions>()
+        .ok_or("DataError: Unable to get subscriptions")?;
+    subs.retain(|s| !(s.user == sub.user && s.server == sub.server));
+    subs.push(sub.clone());
+
+    let conn = data
+        .get_mut::<DbConnection>()
+        .ok_or("DataError: Unable to get database connection")?;
+    db::write_subscription(conn, &sub).await?;
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!(
+                "I'll {} when `{}` has {} or more players, at most once every {} minutes",
+                if sub.channel.is_some() {
+                    "mention you here"
+                } else {
+                    "DM you"
+                },
+                sub.server,
+                sub.min_players,
+                sub.cooldown / 60
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+fn unnotify_help() -> String {
+    "Stop getting notified about a server, or about every server if none is given.".into()
+}
+
+#[poise::command(
+    slash_command,
+    help_text_fn = "unnotify_help",
+    category = "Notifications"
+)]
+pub async fn unnotify(
+    ctx: Context<'_>,
+    #[description = "Server identifier, defaults to all"] name: Option<String>,
+) -> Result<(), Error> {
+    let user = ctx.author().id;
+    let mut data = ctx.serenity_context().data.write().await;
+
+    let subs = data
+        .get_mut::<Subscriptions>()
+        .ok_or("DataError: Unable to get subscriptions")?;
+
+    let mut removed = vec![];
+    subs.retain(|s| {
+        let matches = s.user == user && name.as_ref().is_none_or(|n| *n == s.server);
+        if matches {
+            removed.push(s.server.clone());
+        }
+        !matches
+    });
+
+    let conn = data
+        .get_mut::<DbConnection>()
+        .ok_or("DataError: Unable to get database connection")?;
+    db::remove_subscriptions(conn, user, name.as_deref()).await?;
+
+    let content = if removed.is_empty() {
+        "You weren't subscribed to anything".to_string()
+    } else {
+        removed
+            .iter()
+            .fold("Unsubscribed from".to_string(), |mut output, server| {
+                _ = write!(output, " `{server}`");
+                output
+            })
+    };
+
+    ctx.send(CreateReply::default().content(content).ephemeral(true))
+        .await?;
+
+    Ok(())
+}
+
+pub mod db {
+    use super::{Subscription, SubscriptionsValue};
+    use crate::Error;
+    use poise::serenity_prelude::{ChannelId, UserId};
+    use sqlx::SqliteConnection;
+
+    pub async fn read_subscriptions(
+        conn: &mut SqliteConnection,
+    ) -> Result<SubscriptionsValue, Error> {
+        struct Fetch {
+            user_id: String,
+            server_name: String,
+            min_players: i64,
+            channel_id: Option<String>,
+            cooldown: i64,
+            last_notified: Option<i64>,
+        }
+
+        let subs = sqlx::query_as!(
+            Fetch,
+            "SELECT user_id, server_name, min_players, channel_id, cooldown, last_notified FROM player_subscriptions"
+        )
+        .fetch_all(conn)
+        .await?;
+
+        Ok(subs
+            .into_iter()
+            .filter_map(|v| {
+                Some(Subscription {
+                    user: UserId::new(v.user_id.parse().ok()?),
+                    server: v.server_name,
+                    min_players: v.min_players,
+                    channel: v
+                        .channel_id
+                        .and_then(|c| c.parse().ok())
+                        .map(ChannelId::new),
+                    cooldown: v.cooldown,
+                    last_notified: v.last_notified,
+                })
+            })
+            .collect())
+    }
+
+    pub async fn write_subscription(
+        conn: &mut SqliteConnection,
+        sub: &Subscription,
+    ) -> Result<(), Error> {
+        let user = sub.user.to_string();
+        let channel = sub.channel.map(|c| c.to_string());
+
+        sqlx::query!(
+            "INSERT INTO player_subscriptions (user_id, server_name, min_players, channel_id, cooldown, last_notified) VALUES (?, ?, ?, ?, ?, ?)
+ON CONFLICT(user_id, server_name) DO UPDATE
+SET min_players = excluded.min_players,
+    channel_id = excluded.channel_id,
+    cooldown = excluded.cooldown,
+    last_notified = excluded.last_notified",
+            user,
+            sub.server,
+            sub.min_players,
+            channel,
+            sub.cooldown,
+            sub.last_notified
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_last_notified(
+        conn: &mut SqliteConnection,
+        user: UserId,
+        server: &str,
+        time: i64,
+    ) -> Result<(), Error> {
+        let user = user.to_string();
+
+        sqlx::query!(
+            "UPDATE player_subscriptions SET last_notified = ? WHERE user_id = ? AND server_name = ?",
+            time,
+            user,
+            server
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Remove the subscriptions of `user` to `server`, or to every server
+    pub async fn remove_subscriptions(
+        conn: &mut SqliteConnection,
+        user: UserId,
+        server: Option<&str>,
+    ) -> Result<(), Error> {
+        let user = user.to_string();
+
+        sqlx::query!(
+            "DELETE FROM player_subscriptions WHERE user_id = ? AND (?2 IS NULL OR server_name = ?2)",
+            user,
+            server
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_server_subscriptions(
+        conn: &mut SqliteConnection,
+        server: &str,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            "DELETE FROM player_subscriptions WHERE server_name = ?",
+            server
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sub(min_players: i64, last_notified: Option<i64>) -> Subscription {
+        Subscription {
+            user: UserId::new(1),
+            server: "meow".into(),
+            min_players,
+            channel: None,
+            cooldown: 3600,
+            last_notified,
+        }
+    }
+
+    #[test]
+    fn crossing_threshold() {
+        let s = sub(3, None);
+
+        assert!(s.due(2, 3, 0));
+        assert!(s.due(0, 5, 0));
+        // already there, or not there yet
+        assert!(!s.due(3, 4, 0));
+        assert!(!s.due(1, 2, 0));
+        assert!(!s.due(4, 2, 0));
+    }
+
+    #[test]
+    fn cooldown() {
+        let s = sub(3, Some(1000));
+
+        assert!(!s.due(2, 3, 1000 + 3599));
+        assert!(s.due(2, 3, 1000 + 3600));
+    }
+}
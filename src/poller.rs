@@ -0,0 +1,188 @@
+use crate::server_info::query_server_info;
+use crate::server_info::DownThresholds;
+use crate::server_info::Info;
+use crate::server_info::PollState;
+use crate::servers::Server;
+use crate::socket::create_sockets;
+use crate::socket::Sockets;
+use crate::Error;
+use poise::serenity_prelude::prelude::TypeMapKey;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time;
+use tokio::time::MissedTickBehavior;
+
+/// How long a reader waits for the first poll of a server
+const FIRST_POLL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Background task querying one server at a fixed interval, the latest result is published
+/// through a watch channel so readers never wait on the network
+pub struct Poller {
+    info: watch::Sender<Option<Info>>,
+    task: JoinHandle<()>,
+}
+
+impl Poller {
+    pub async fn start(
+        name: String,
+        addr: &str,
+        interval: Duration,
+        thresholds: DownThresholds,
+    ) -> Result<Self, Error> {
+        let socks = create_sockets(addr).await?;
+        let (info, _) = watch::channel(None);
+
+        let task = tokio::spawn(poll_loop(name, socks, interval, thresholds, info.clone()));
+
+        Ok(Poller { info, task })
+    }
+
+    /// The latest snapshot, waits for the first poll if there hasn't been one yet
+    pub async fn info(&self) -> Result<Info, Error> {
+        let mut rx = self.info.subscribe();
+        let info = time::timeout(FIRST_POLL_TIMEOUT, rx.wait_for(Option::is_some)).await??;
+
+        Ok(info.clone().ok_or("PollerError: No snapshot")?)
+    }
+
+    /// The latest snapshot without waiting, `None` before the first poll
+    pub fn latest(&self) -> Option<Info> {
+        self.info.borrow().clone()
+    }
+}
+
+impl Drop for Poller {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn poll_loop(
+    name: String,
+    socks: Sockets,
+    interval: Duration,
+    thresholds: DownThresholds,
+    info: watch::Sender<Option<Info>>,
+) {
+    let mut interval = time::interval(interval);
+    // a slow server shouldn't be hammered with queries to catch up
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let mut state = PollState::default();
+
+    loop {
+        interval.tick().await;
+
+        let previous = info.borrow().clone();
+
+        match query_server_info(&socks, &name, previous.as_ref(), &mut state, &thresholds).await {
+            Ok(new) => {
+                info.send_replace(Some(new));
+            }
+            Err(e) => eprintln!("Error polling {name}: {e}"),
+        }
+    }
+}
+
+pub struct Pollers;
+pub type PollersValue = HashMap<String, Poller>;
+impl TypeMapKey for Pollers {
+    type Value = PollersValue;
+}
+
+/// Start polling `server`, replacing its old poller
+pub async fn start_poller(pollers: &mut PollersValue, server: &Server) -> Result<(), Error> {
+    let interval = Duration::from_secs(server.poll_interval.max(1) as u64);
+    let thresholds = DownThresholds {
+        failures: server.down_failures.max(1) as u32,
+        recoveries: server.down_recoveries.max(1) as u32,
+    };
+
+    let poller = Poller::start(server.name.clone(), &server.addr, interval, thresholds).await?;
+
+    pollers.insert(server.name.clone(), poller);
+
+    Ok(())
+}
+
+/// The latest snapshot of server `name`
+pub async fn get_server_info(pollers: &PollersValue, name: &str) -> Result<Info, Error> {
+    pollers
+        .get(name)
+        .ok_or(format!("PollerError: Server {} isn't being polled", name))?
+        .info()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csgo_server::testing::{Behaviour, FakeServer, FakeServerConfig};
+
+    async fn start(name: &str, server: &FakeServer) -> Poller {
+        let thresholds = DownThresholds {
+            failures: 1,
+            recoveries: 1,
+        };
+
+        Poller::start(
+            name.into(),
+            &server.addr.to_string(),
+            Duration::from_millis(50),
+            thresholds,
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn publishes_polls() {
+        let server = FakeServer::start(FakeServerConfig::default())
+            .await
+            .unwrap();
+        let poller = start("test_poller", &server).await;
+
+        assert!(matches!(poller.info().await.unwrap(), Info::ServerUp(_)));
+
+        server.update(|c| c.info.map = "dz_county".into());
+
+        let mut rx = poller.info.subscribe();
+        let changed = rx.wait_for(
+            |info| matches!(info, Some(Info::ServerUp(up)) if &*up.server_info.map == "dz_county"),
+        );
+
+        time::timeout(Duration::from_secs(2), changed)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn slow_server_is_isolated() {
+        let slow = FakeServer::start(FakeServerConfig {
+            behaviour: Behaviour::Silent,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let fast = FakeServer::start(FakeServerConfig::default())
+            .await
+            .unwrap();
+
+        let slow = start("test_slow", &slow).await;
+        // let the slow poller get stuck waiting for a reply first
+        time::sleep(Duration::from_millis(100)).await;
+        let fast = start("test_fast", &fast).await;
+
+        let info = time::timeout(Duration::from_secs(1), fast.info())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(info, Info::ServerUp(_)));
+
+        // still waiting on its first poll
+        assert!(slow.info.borrow().is_none());
+    }
+}
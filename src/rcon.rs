@@ -0,0 +1,198 @@
+use crate::db::DbConnection;
+use crate::privilege_check;
+use crate::servers::db::write_server;
+use crate::servers::Servers;
+use crate::Context;
+use crate::Error;
+use csgo_server::rcon::Rcon;
+use poise::CreateReply;
+use std::time::Duration;
+
+const RCON_TIMEOUT: Duration = Duration::from_secs(5);
+// discord message limit
+const MAX_MESSAGE: usize = 2000;
+// a long command still has to leave room for its output
+const MAX_COMMAND: usize = 500;
+
+/// Cut `s` down to at most `max` bytes including `marker`, which is appended if anything was cut
+fn truncate(s: &mut String, max: usize, marker: &str) {
+    if s.len() <= max {
+        return;
+    }
+
+    let mut end = max.saturating_sub(marker.len());
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    s.truncate(end);
+    s.push_str(marker);
+}
+
+/// The reply to `command` on `name`, kept under discord's message limit
+fn format_reply(command: &str, name: &str, output: &str) -> String {
+    let mut command = command.to_string();
+    truncate(&mut command, MAX_COMMAND, "...");
+
+    let header = format!("`{}` on `{}`\n```\n", command, name);
+    let footer = "\n```";
+
+    let mut output = output.trim().to_string();
+    truncate(
+        &mut output,
+        MAX_MESSAGE.saturating_sub(header.len() + footer.len()),
+        "\n...",
+    );
+
+    // discord breaks formatting of codeblocks if it's empty
+    if output.is_empty() {
+        output.push(' ');
+    }
+
+    format!("{header}{output}{footer}")
+}
+
+/// Run `command` on the server called `name` and reply with the output
+async fn run(ctx: Context<'_>, name: &String, command: &str) -> Result<(), Error> {
+    let (addr, password) = {
+        let data = ctx.serenity_context().data.read().await;
+
+        let server = data
+            .get::<Servers>()
+            .ok_or("DataError: Unable to get servers")?
+            .get(name)
+            .ok_or(format!("ServerError: Unable to get server {}", name))?;
+
+        let password = server
+            .rcon_password
+            .clone()
+            .ok_or(format!("Server {} has no RCON password set", name))?;
+
+        (server.addr.clone(), password)
+    };
+
+    ctx.defer_ephemeral().await?;
+
+    let mut rcon = Rcon::connect(&addr, &password, RCON_TIMEOUT).await?;
+    let output = rcon.exec(command).await?;
+
+    ctx.send(
+        CreateReply::default()
+            .content(format_reply(command, name, &output))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+fn rcon_help() -> String {
+    "Run a console command on a server through RCON.
+Requires admin privileges."
+        .into()
+}
+
+#[poise::command(
+    slash_command,
+    check = "privilege_check",
+    category = "RCON",
+    help_text_fn = "rcon_help"
+)]
+pub async fn rcon(
+    ctx: Context<'_>,
+    #[description = "Server identifier"] name: String,
+    #[description = "Console command"] command: String,
+) -> Result<(), Error> {
+    run(ctx, &name, &command).await
+}
+
+#[poise::command(slash_command, check = "privilege_check", category = "RCON")]
+pub async fn changelevel(
+    ctx: Context<'_>,
+    #[description = "Server identifier"] name: String,
+    #[description = "Map name, e.g. dz_sirocco"] map: String,
+) -> Result<(), Error> {
+    if map.is_empty() || !map.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("Invalid map name {map}").into());
+    }
+
+    run(ctx, &name, &format!("changelevel {map}")).await
+}
+
+#[poise::command(slash_command, check = "privilege_check", category = "RCON")]
+pub async fn kick(
+    ctx: Context<'_>,
+    #[description = "Server identifier"] name: String,
+    #[description = "Player name"] player: String,
+) -> Result<(), Error> {
+    // the name is quoted, so it can't contain quotes or break out into another command
+    let player = player.replace(['"', ';', '\n'], "");
+
+    run(ctx, &name, &format!("kick \"{player}\"")).await
+}
+
+fn set_rcon_password_help() -> String {
+    "Set the RCON password of a server, leave the password empty to remove it.
+Requires admin privileges."
+        .into()
+}
+
+#[poise::command(
+    slash_command,
+    check = "privilege_check",
+    category = "RCON",
+    help_text_fn = "set_rcon_password_help"
+)]
+pub async fn set_rcon_password(
+    ctx: Context<'_>,
+    #[description = "Server identifier"] name: String,
+    #[description = "RCON password"] password: Option<String>,
+) -> Result<(), Error> {
+    let mut data = ctx.serenity_context().data.write().await;
+
+    let server = data
+        .get_mut::<Servers>()
+        .ok_or("DataError: Unable to get servers")?
+        .get_mut(&name)
+        .ok_or(format!("ServerError: Unable to get server {}", name))?;
+    server.rcon_password = password.filter(|p| !p.is_empty());
+
+    let server = server.clone();
+
+    let conn = data
+        .get_mut::<DbConnection>()
+        .ok_or("DataError: Unable to get database connection")?;
+    write_server(&server, conn).await?;
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!("Updated RCON password of {}", name))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reply_fits() {
+        let reply = format_reply("status", "meow", "  hostname: meow\n");
+        assert_eq!(reply, "`status` on `meow`\n```\nhostname: meow\n```");
+
+        assert_eq!(
+            format_reply("echo", "meow", ""),
+            "`echo` on `meow`\n```\n \n```"
+        );
+
+        let long = "ä".repeat(3000);
+        for (command, output) in [("status", long.as_str()), (long.as_str(), long.as_str())] {
+            let reply = format_reply(command, "meow", output);
+
+            assert!(reply.len() <= MAX_MESSAGE, "{} bytes", reply.len());
+            assert!(reply.ends_with("\n...\n```"));
+        }
+    }
+}
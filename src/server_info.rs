@@ -1,34 +1,63 @@
-use csgo_server::{info::ServerInfo, players::Players};
+use csgo_server::{
+    info::ServerInfo,
+    players::{Player, Players},
+    rules::Rules,
+};
 use once_cell::sync::Lazy;
 use serde::Serialize;
-use std::collections::HashMap;
-use std::sync::Arc;
 use std::time::Duration;
 use std::time::SystemTime;
 use std::time::UNIX_EPOCH;
-use tokio::net::UdpSocket;
-use tokio::sync::RwLock;
-use tokio::sync::RwLockWriteGuard;
+use tokio::sync::broadcast;
 
 use csgo_server::info;
 use csgo_server::players;
+use csgo_server::request::QueryOptions;
+use csgo_server::rules;
 
-use crate::socket::ServerSocketValue;
+use crate::socket::Sockets;
 use crate::Error;
 
 #[derive(Debug, Clone, Serialize)]
 pub struct ServerUp {
     pub server_info: ServerInfo,
     pub players: Players,
+    /// `None` if the server doesn't answer `A2S_RULES`, CS:GO only does with `host_rules_show 1`
+    pub rules: Option<Rules>,
     timestamp: SystemTime,
     pub elapsed: Duration,
     pub image: Option<Box<str>>,
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub enum DownReason {
+    /// The server didn't answer at all
+    Unreachable,
+    /// The server answered, but with something we couldn't parse
+    MalformedReply(String),
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct ServerDown {
     pub since: SystemTime,
-    pub ping_sent: bool,
+    pub reason: DownReason,
+}
+
+impl ServerDown {
+    fn new(error: &csgo_server::Error) -> Self {
+        let reason = if error.is_unreachable() {
+            eprintln!("Error: {error}, assuming server is down");
+            DownReason::Unreachable
+        } else {
+            eprintln!("Error: {error}, server sent a malformed reply");
+            DownReason::MalformedReply(error.to_string())
+        };
+
+        ServerDown {
+            since: SystemTime::now(),
+            reason,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -37,8 +66,51 @@ pub enum Info {
     ServerDown(ServerDown),
 }
 
-pub static INFO: Lazy<Arc<RwLock<HashMap<String, Info>>>> =
-    Lazy::new(|| Arc::new(RwLock::new(HashMap::new())));
+/// Difference between two consecutive polls of a server, only counting real players
+#[derive(Debug, Clone)]
+pub struct PlayerDiff {
+    pub server: String,
+    pub time: SystemTime,
+    pub joined: Vec<Player>,
+    pub left: Vec<Player>,
+    /// Everyone on the server after this poll, empty if the server went down
+    pub players: Players,
+    /// `None` if the server went down
+    pub map: Option<Box<str>>,
+}
+
+/// Every poll publishes a `PlayerDiff` here, subscribe to get join/leave events
+pub static PLAYER_DIFFS: Lazy<broadcast::Sender<PlayerDiff>> =
+    Lazy::new(|| broadcast::channel(256).0);
+
+fn publish_diff(name: &str, previous: Option<&Info>, players: Players, map: Option<Box<str>>) {
+    let previous = match previous {
+        Some(Info::ServerUp(v)) => v.players.clone().real().0,
+        _ => vec![],
+    };
+
+    let joined = players
+        .0
+        .iter()
+        .filter(|p| !previous.iter().any(|o| o.name == p.name))
+        .cloned()
+        .collect();
+
+    let left = previous
+        .into_iter()
+        .filter(|o| !players.0.iter().any(|p| p.name == o.name))
+        .collect();
+
+    // nobody listening is fine
+    _ = PLAYER_DIFFS.send(PlayerDiff {
+        server: name.to_string(),
+        time: SystemTime::now(),
+        joined,
+        left,
+        players,
+        map,
+    });
+}
 
 pub struct MapData {
     map: Box<str>,
@@ -103,122 +175,327 @@ impl MapData {
     }
 }
 
-static MAP_DATA: Lazy<Arc<RwLock<HashMap<String, MapData>>>> =
-    Lazy::new(|| Arc::new(RwLock::new(HashMap::new())));
+fn map_data_setup<'a>(map_data: &'a mut Option<MapData>, server_info: &ServerInfo) -> &'a MapData {
+    let data = map_data.get_or_insert_with(|| MapData::new(&server_info.map));
 
-async fn map_data_setup(name: &String, server_info: &ServerInfo) {
-    let mut data = MAP_DATA.write().await;
+    if data.map != server_info.map {
+        data.update(&server_info.map);
+    }
 
-    match data.get_mut(name) {
-        Some(v) => {
-            if v.map != server_info.map {
-                v.update(&server_info.map);
-            }
-        }
-        None => {
-            data.insert(name.clone(), MapData::new(&server_info.map));
-        }
+    data
+}
+
+/// How long to wait before asking a server that didn't answer `A2S_RULES` again
+const RULES_RETRY: Duration = Duration::from_secs(600);
+
+/// Rules run next to the other queries, a server hiding them shouldn't slow down every poll
+fn rules_options() -> QueryOptions {
+    QueryOptions {
+        deadline: Duration::from_secs(1),
+        timeout: Duration::from_secs(1),
+        retries: 0,
+        ..Default::default()
     }
 }
 
 async fn sinfo(
     name: &String,
-    socks: &(UdpSocket, UdpSocket),
-) -> Result<(ServerInfo, Players), Error> {
-    let server_info = info::get_server_info(&socks.0).await?;
-    let players = players::get_players(&socks.1).await?;
+    socks: &Sockets,
+    state: &mut PollState,
+) -> Result<(ServerInfo, Players, Option<Rules>), csgo_server::Error> {
+    let options = QueryOptions::default();
+
+    let ask_rules = match state.rules_failed {
+        Some(failed) => failed.elapsed().unwrap_or_default() >= RULES_RETRY,
+        None => true,
+    };
+
+    let queries = async {
+        let server_info = info::get_server_info(&socks.0, &options).await?;
+        let players = players::get_players(&socks.1, server_info.id, &options).await?;
+
+        Ok::<_, csgo_server::Error>((server_info, players))
+    };
+
+    let rules_query = async {
+        if ask_rules {
+            Some(rules::get_rules(&socks.2, &rules_options()).await)
+        } else {
+            None
+        }
+    };
 
+    let (queries, rules) = tokio::join!(queries, rules_query);
+    let (server_info, players) = queries?;
 
-    // 	return Err("ligma balls".into());
-    // }
+    // rules are optional, a server that hides them is still up
+    let rules = match rules {
+        Some(Ok(v)) => {
+            if state.rules_failed.take().is_some() {
+                eprintln!("{name} answers rules again");
+            }
+            Some(v)
+        }
+        Some(Err(e)) => {
+            if state.rules_failed.is_none() {
+                eprintln!(
+                    "Unable to get rules of {name}: {e}, asking again every {} minutes",
+                    RULES_RETRY.as_secs() / 60
+                );
+            }
+            state.rules_failed = Some(SystemTime::now());
+            None
+        }
+        None => None,
+    };
 
-    Ok((server_info, players))
+    Ok((server_info, players, rules))
 }
 
-async fn setup_info(
-    infomap: &mut RwLockWriteGuard<'_, HashMap<String, Info>>,
-    name: &String,
+fn setup_info(
+    name: &str,
+    previous: Option<&Info>,
+    map_data: &mut Option<MapData>,
     server_info: ServerInfo,
     players: Players,
+    rules: Option<Rules>,
 ) -> Result<Info, Error> {
-    map_data_setup(name, &server_info).await;
-    // if this doesn't unwrap i will explode
-    let mapdata = MAP_DATA.read().await;
-    let mapdata = mapdata.get(name).unwrap();
+    let mapdata = map_data_setup(map_data, &server_info);
 
     let now = SystemTime::now();
 
-    let info = Info::ServerUp(ServerUp {
+    let up = ServerUp {
         server_info,
         players,
+        rules,
         timestamp: now,
         elapsed: now.duration_since(mapdata.time)?,
         image: mapdata.image.clone(),
-    });
+    };
 
-    infomap.insert(name.clone(), info.clone());
+    publish_diff(
+        name,
+        previous,
+        up.players.clone().real(),
+        Some(up.server_info.map.clone()),
+    );
 
-    Ok(info)
+    Ok(Info::ServerUp(up))
 }
 
-pub async fn get_server_info(socks: &ServerSocketValue, name: &String) -> Result<Info, Error> {
-    let socks = socks
-        .get(name)
-        .ok_or(format!("SocketError: Unable to get socket of {}", name))?;
-
-    let mut infomap = INFO.write().await;
+/// When to flip a server between up and down, so a dropped packet doesn't count as downtime
+#[derive(Debug, Clone, Copy)]
+pub struct DownThresholds {
+    /// Consecutive failed queries before the server is down
+    pub failures: u32,
+    /// Consecutive successful queries before a down server is up again
+    pub recoveries: u32,
+}
 
-    match infomap.get(name) {
-        Some(Info::ServerUp(v)) => {
-            if let Ok(duration) = SystemTime::now().duration_since(v.timestamp) {
-                if duration.as_secs() >= 5 {
-                    return match sinfo(name, socks).await {
-                        Ok((server_info, players)) => {
-                            setup_info(&mut infomap, name, server_info, players).await
-                        }
-                        Err(e) => {
-                            eprintln!("Error: {e:#?}, assuming server is down");
+/// State kept between polls of one server
+#[derive(Default)]
+pub struct PollState {
+    map_data: Option<MapData>,
+    failures: u32,
+    successes: u32,
+    first_failure: Option<SystemTime>,
+    /// When the server last didn't answer `A2S_RULES`, they aren't asked for until `RULES_RETRY` later
+    rules_failed: Option<SystemTime>,
+}
 
-                            let down = Info::ServerDown(ServerDown {
-                                since: SystemTime::now(),
-                                ping_sent: false,
-                            });
+/// Query server `name`, `previous` and `state` are left by the last poll of it
+///
+/// Nothing here is shared between servers, so a slow server only holds up its own poller
+pub async fn query_server_info(
+    socks: &Sockets,
+    name: &String,
+    previous: Option<&Info>,
+    state: &mut PollState,
+    thresholds: &DownThresholds,
+) -> Result<Info, Error> {
+    match sinfo(name, socks, state).await {
+        Ok((server_info, players, rules)) => {
+            state.failures = 0;
+            state.first_failure = None;
+            state.successes += 1;
+
+            match previous {
+                Some(Info::ServerDown(v)) if state.successes < thresholds.recoveries => {
+                    Ok(Info::ServerDown(v.clone()))
+                }
+                _ => setup_info(
+                    name,
+                    previous,
+                    &mut state.map_data,
+                    server_info,
+                    players,
+                    rules,
+                ),
+            }
+        }
+        Err(e) => {
+            state.successes = 0;
+            state.failures += 1;
+            let first_failure = *state.first_failure.get_or_insert_with(SystemTime::now);
+
+            match previous {
+                // still down, keep when it went down and whether anyone was told
+                Some(Info::ServerDown(v)) => Ok(Info::ServerDown(v.clone())),
+                // show the last good data until it's clearly down
+                Some(Info::ServerUp(v)) if state.failures < thresholds.failures => {
+                    eprintln!("Error: {e}, {} failed queries of {name}", state.failures);
+                    Ok(Info::ServerUp(v.clone()))
+                }
+                _ => {
+                    if let Some(Info::ServerUp(_)) = previous {
+                        publish_diff(name, previous, Players(vec![]), None);
+                    }
 
-			    infomap.insert(name.clone(), down.clone());
+                    let mut down = ServerDown::new(&e);
+                    down.since = first_failure;
 
-                            Ok(down)
-                        }
-                    };
+                    Ok(Info::ServerDown(down))
                 }
             }
-
-            Ok(Info::ServerUp(v.clone()))
         }
-        Some(Info::ServerDown(v)) => {
-            if let Ok((server_info, players)) = sinfo(name, socks).await {
-                setup_info(&mut infomap, name, server_info, players).await
-            } else {
-                Ok(Info::ServerDown(v.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::create_sockets;
+    use csgo_server::testing::{Behaviour, FakeServer, FakeServerConfig};
+
+    async fn query(server: &FakeServer, name: &str) -> Info {
+        let socks = create_sockets(server.addr).await.unwrap();
+
+        let thresholds = DownThresholds {
+            failures: 1,
+            recoveries: 1,
+        };
+
+        query_server_info(
+            &socks,
+            &name.to_string(),
+            None,
+            &mut PollState::default(),
+            &thresholds,
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn server_up() {
+        let config = FakeServerConfig::default();
+        let server = FakeServer::start(config.clone()).await.unwrap();
+
+        match query(&server, "test_up").await {
+            Info::ServerUp(up) => {
+                assert_eq!(up.server_info, config.info);
+                assert_eq!(up.players, config.players);
             }
+            Info::ServerDown(down) => panic!("server reported down: {down:?}"),
         }
-        _ => {
-            return match sinfo(name, socks).await {
-                Ok((server_info, players)) => {
-                    setup_info(&mut infomap, name, server_info, players).await
-                }
-                Err(e) => {
-                    eprintln!("Error: {e:#?}, assuming server is down");
+    }
 
-                    let down = Info::ServerDown(ServerDown {
-                        since: SystemTime::now(),
-                        ping_sent: false,
-                    });
+    #[tokio::test]
+    async fn malformed_reply() {
+        let config = FakeServerConfig {
+            behaviour: Behaviour::Garbage(vec![0xFF, 0xFF, 0xFF, 0xFF, b'X']),
+            ..Default::default()
+        };
+        let server = FakeServer::start(config).await.unwrap();
+
+        match query(&server, "test_malformed").await {
+            Info::ServerDown(ServerDown {
+                reason: DownReason::MalformedReply(_),
+                ..
+            }) => (),
+            info => panic!("expected a malformed reply, got {info:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn hysteresis() {
+        let server = FakeServer::start(FakeServerConfig::default())
+            .await
+            .unwrap();
+        let socks = create_sockets(server.addr).await.unwrap();
+        let name = "test_hysteresis".to_string();
+
+        let mut state = PollState::default();
+        let thresholds = DownThresholds {
+            failures: 2,
+            recoveries: 2,
+        };
 
-		    infomap.insert(name.clone(), down.clone());
+        let mut info: Option<Info> = None;
+        let mut poll = async |info: &mut Option<Info>| {
+            let new = query_server_info(&socks, &name, info.as_ref(), &mut state, &thresholds)
+                .await
+                .unwrap();
+            let up = matches!(new, Info::ServerUp(_));
+            *info = Some(new);
+            up
+        };
 
-                    Ok(down)
-                }
-            }
+        assert!(poll(&mut info).await);
+
+        server.update(|c| c.behaviour = Behaviour::Garbage(vec![1, 2, 3, 4, 5]));
+        assert!(poll(&mut info).await, "down after a single failure");
+        assert!(!poll(&mut info).await);
+
+        server.update(|c| c.behaviour = Behaviour::Normal);
+        assert!(!poll(&mut info).await, "up after a single success");
+        assert!(poll(&mut info).await);
+    }
+
+    #[tokio::test]
+    async fn hidden_rules() {
+        let server = FakeServer::start(FakeServerConfig {
+            hide_rules: true,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let socks = create_sockets(server.addr).await.unwrap();
+        let name = "test_hidden_rules".to_string();
+
+        let mut state = PollState::default();
+        let thresholds = DownThresholds {
+            failures: 1,
+            recoveries: 1,
+        };
+
+        let info = query_server_info(&socks, &name, None, &mut state, &thresholds)
+            .await
+            .unwrap();
+
+        match info {
+            Info::ServerUp(up) => assert!(up.rules.is_none()),
+            Info::ServerDown(down) => panic!("server reported down: {down:?}"),
+        }
+        assert!(state.rules_failed.is_some());
+        // asked once, without a retry
+        assert_eq!(server.received(b'V'), 1);
+
+        // not asked again until RULES_RETRY is up
+        server.update(|c| c.hide_rules = false);
+        query_server_info(&socks, &name, None, &mut state, &thresholds)
+            .await
+            .unwrap();
+        assert_eq!(server.received(b'V'), 1);
+
+        state.rules_failed = Some(SystemTime::now() - RULES_RETRY);
+        match query_server_info(&socks, &name, None, &mut state, &thresholds)
+            .await
+            .unwrap()
+        {
+            Info::ServerUp(up) => assert!(up.rules.is_some()),
+            Info::ServerDown(down) => panic!("server reported down: {down:?}"),
         }
+        assert!(state.rules_failed.is_none());
     }
 }
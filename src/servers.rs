@@ -1,21 +1,41 @@
+use crate::alerts::db::remove_alert;
+use crate::alerts::Alerts;
 use crate::db::DbConnection;
+use crate::down_detector::db::write_notified;
+use crate::history::db::remove_server_samples;
 use crate::privilege_check;
+use crate::notify::db::remove_server_subscriptions;
+use crate::notify::Subscriptions;
 use crate::servers::db::remove_server;
+use crate::watch::db::remove_server_watches;
+use crate::watch::Watches;
+use crate::webhooks::db::remove_server_webhooks;
+use crate::webhooks::Webhooks;
 use crate::Context;
 use crate::Error;
-use crate::ServerSocket;
+use crate::poller::Pollers;
+use crate::poller::start_poller;
 use poise::serenity_prelude::prelude::TypeMapKey;
 use poise::CreateReply;
 use std::collections::HashMap;
 use std::fmt::Write;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Server {
     pub name: String,
     pub addr: String,
     pub max_player_count: i64, // this could be u8 but sqlite is dumb
     pub legacy: bool,
     pub allow_upload_required: bool,
+    pub rcon_password: Option<String>,
+    /// Seconds between queries of the server
+    pub poll_interval: i64,
+    /// Consecutive failed queries before the server is down
+    pub down_failures: i64,
+    /// Consecutive successful queries before the server is back up
+    pub down_recoveries: i64,
+    /// Seconds the server has to be down before anyone is notified
+    pub down_grace: i64,
 }
 
 pub struct Servers;
@@ -43,6 +63,7 @@ pub async fn create_server(
     #[description = "Maximum player count"] max_player_count: Option<u8>,
     #[description = "Is this a legacy CS:GO server"] legacy: Option<bool>,
     #[description = "Does the server require sv_allowupload 1"] allow_upload_required: Option<bool>,
+    #[description = "Seconds between queries, defaults to 5"] poll_interval: Option<u32>,
 ) -> Result<(), Error> {
     let max_player_count: i64 = match max_player_count {
         Some(v) => v as i64,
@@ -52,21 +73,42 @@ pub async fn create_server(
     let legacy = legacy.unwrap_or(true);
     let allow_upload_required = allow_upload_required.unwrap_or(false);
 
+    let mut data = ctx.serenity_context().data.write().await;
+
+    // keep the rcon password, poll interval and down detection when updating an existing server
+    let existing = data.get::<Servers>().and_then(|s| s.get(&name));
+    let rcon_password = existing.and_then(|s| s.rcon_password.clone());
+    let poll_interval = poll_interval
+        .map(|v| v.max(1) as i64)
+        .or(existing.map(|s| s.poll_interval))
+        .unwrap_or(5);
+    let (down_failures, down_recoveries, down_grace) = existing
+        .map(|s| (s.down_failures, s.down_recoveries, s.down_grace))
+        .unwrap_or((3, 2, 120));
+
     let server = Server {
         name: name.clone(),
         addr,
         max_player_count,
         legacy,
         allow_upload_required,
+        rcon_password,
+        poll_interval,
+        down_failures,
+        down_recoveries,
+        down_grace,
     };
 
-    let mut data = ctx.serenity_context().data.write().await;
-
     let conn = data
         .get_mut::<DbConnection>()
         .ok_or("DataError: Unable to database connection")?;
     db::write_server(&server, conn).await?;
 
+    let pollers = data
+        .get_mut::<Pollers>()
+        .ok_or("DataError: Unable to get pollers")?;
+    start_poller(pollers, &server).await?;
+
     let servers = data
         .get_mut::<Servers>()
         .ok_or("DataError: Unable to get servers")?;
@@ -94,15 +136,43 @@ pub async fn delete_server(
         .ok_or("DataError: Unable to get servers")?;
     servers.retain(|s_name, _addr| *s_name != name);
 
-    let sockets = data
-        .get_mut::<ServerSocket>()
-        .ok_or("DataError: Unable to get server sockets")?;
-    sockets.remove(&name);
+    // dropping the poller stops it
+    let pollers = data
+        .get_mut::<Pollers>()
+        .ok_or("DataError: Unable to get pollers")?;
+    pollers.remove(&name);
+
+    let alerts = data
+        .get_mut::<Alerts>()
+        .ok_or("DataError: Unable to get alerts")?;
+    alerts.remove(&name);
+
+    let webhooks = data
+        .get_mut::<Webhooks>()
+        .ok_or("DataError: Unable to get webhooks")?;
+    webhooks.retain(|h| h.server != name);
+
+    let subscriptions = data
+        .get_mut::<Subscriptions>()
+        .ok_or("DataError: Unable to get subscriptions")?;
+    subscriptions.retain(|s| s.server != name);
+
+    let watches = data
+        .get_mut::<Watches>()
+        .ok_or("DataError: Unable to get watches")?;
+    watches.retain(|w| w.server.as_ref() != Some(&name));
 
     let conn = data
         .get_mut::<DbConnection>()
         .ok_or("DataError: Unable to get database connection")?;
     remove_server(&name, conn).await?;
+    remove_alert(conn, &name).await?;
+    remove_server_webhooks(conn, &name).await?;
+    remove_server_subscriptions(conn, &name).await?;
+    remove_server_watches(conn, &name).await?;
+    remove_server_samples(conn, &name).await?;
+    // a new server with the same name shouldn't be announced back up
+    write_notified(conn, &name, false).await?;
 
     ctx.send(
         CreateReply::default()
@@ -168,17 +238,27 @@ pub mod db {
 
     pub async fn write_server(server: &Server, conn: &mut SqliteConnection) -> Result<(), Error> {
         sqlx::query!(
-	    "INSERT INTO server_settings (name, addr, max_player_count, legacy, allow_upload_required) VALUES (?, ?, ?, ?, ?)
+	    "INSERT INTO server_settings (name, addr, max_player_count, legacy, allow_upload_required, rcon_password, poll_interval, down_failures, down_recoveries, down_grace) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
 ON CONFLICT(name) DO UPDATE
 SET addr = excluded.addr,
     max_player_count = excluded.max_player_count,
     legacy = excluded.legacy,
-    allow_upload_required = excluded.allow_upload_required",
+    allow_upload_required = excluded.allow_upload_required,
+    rcon_password = excluded.rcon_password,
+    poll_interval = excluded.poll_interval,
+    down_failures = excluded.down_failures,
+    down_recoveries = excluded.down_recoveries,
+    down_grace = excluded.down_grace",
 	    server.name,
 	    server.addr,
 	    server.max_player_count,
 	    server.legacy,
-	    server.allow_upload_required
+	    server.allow_upload_required,
+	    server.rcon_password,
+	    server.poll_interval,
+	    server.down_failures,
+	    server.down_recoveries,
+	    server.down_grace
 	)
 	    .execute(conn)
 	    .await?;
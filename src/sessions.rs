@@ -0,0 +1,417 @@
+use crate::db::DbConnection;
+use crate::server_info::PlayerDiff;
+use crate::time::format_duration;
+use crate::time::unix;
+use crate::tracker::tracker_loop;
+use crate::tracker::Tracker;
+use crate::tracker::FLUSH_INTERVAL;
+use crate::{Context, Error};
+use poise::serenity_prelude as serenity;
+use poise::CreateReply;
+use sqlx::SqliteConnection;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+struct OpenSession {
+    id: i64,
+    peak: i32,
+    flushed: i64,
+}
+
+enum Op {
+    Open { name: Box<str>, score: i32 },
+    Update { id: i64, peak: i32 },
+    Close { id: i64, peak: i32 },
+}
+
+/// Sessions of everyone online, by (server, player)
+#[derive(Default)]
+struct Sessions(HashMap<(String, Box<str>), OpenSession>);
+
+impl Sessions {
+    fn opened(&mut self, server: &str, name: Box<str>, id: i64, score: i32, time: i64) {
+        self.0.insert(
+            (server.to_string(), name),
+            OpenSession {
+                id,
+                peak: score,
+                flushed: time,
+            },
+        );
+    }
+}
+
+impl Tracker for Sessions {
+    type Op = Op;
+    const NAME: &'static str = "session tracker";
+
+    fn diff_ops(&mut self, diff: &PlayerDiff, time: i64) -> Vec<Op> {
+        let open = &mut self.0;
+        let mut ops = vec![];
+
+        // anyone not on the server anymore, this also catches leaves missed while lagging behind
+        let gone: Vec<Box<str>> = open
+            .keys()
+            .filter(|(server, name)| {
+                *server == diff.server
+                    && (diff.left.iter().any(|p| p.name == *name)
+                        || !diff.players.0.iter().any(|p| p.name == *name))
+            })
+            .map(|(_, name)| name.clone())
+            .collect();
+
+        for name in gone {
+            if let Some(s) = open.remove(&(diff.server.clone(), name)) {
+                ops.push(Op::Close {
+                    id: s.id,
+                    peak: s.peak,
+                });
+            }
+        }
+
+        for p in &diff.players.0 {
+            match open.get_mut(&(diff.server.clone(), p.name.clone())) {
+                Some(s) => {
+                    s.peak = s.peak.max(p.score);
+
+                    if time - s.flushed >= FLUSH_INTERVAL {
+                        s.flushed = time;
+                        ops.push(Op::Update {
+                            id: s.id,
+                            peak: s.peak,
+                        });
+                    }
+                }
+                // joined, or was already on when the tracker started
+                None => ops.push(Op::Open {
+                    name: p.name.clone(),
+                    score: p.score,
+                }),
+            }
+        }
+
+        ops
+    }
+
+    async fn apply(
+        &mut self,
+        conn: &mut SqliteConnection,
+        diff: &PlayerDiff,
+        time: i64,
+        op: Op,
+    ) -> Result<(), Error> {
+        match op {
+            Op::Open { name, score } => {
+                let id = db::open_session(conn, &diff.server, &name, time, score).await?;
+                self.opened(&diff.server, name, id, score, time);
+            }
+            Op::Update { id, peak } => db::update_session(conn, id, time, peak).await?,
+            Op::Close { id, peak } => db::close_session(conn, id, time, peak).await?,
+        }
+
+        Ok(())
+    }
+}
+
+pub async fn session_tracker_loop(ctx: Arc<serenity::Context>) {
+    tracker_loop(ctx, Sessions::default()).await
+}
+
+/// Cut `list` down to the whole lines fitting into `max` bytes, player names can be multibyte
+fn truncate_lines(list: &mut String, max: usize) {
+    if list.len() <= max {
+        return;
+    }
+
+    let end = list[..list.floor_char_boundary(max)]
+        .rfind('\n')
+        .unwrap_or(0);
+    list.truncate(end);
+    list.push_str("\n...");
+}
+
+fn sessions_help() -> String {
+    "Show who played on a server recently, and for how long.".into()
+}
+
+#[poise::command(slash_command, help_text_fn = "sessions_help")]
+pub async fn sessions(
+    ctx: Context<'_>,
+    #[description = "Server identifier"] name: String,
+    #[description = "Only show this player"] player: Option<String>,
+    #[description = "How many hours to look back, defaults to 24"] hours: Option<u32>,
+) -> Result<(), Error> {
+    let now = unix(SystemTime::now())?;
+    let since = now - hours.unwrap_or(24) as i64 * 3600;
+
+    let rows = {
+        let mut data = ctx.serenity_context().data.write().await;
+        let conn = data
+            .get_mut::<DbConnection>()
+            .ok_or("DataError: Unable to get database connection")?;
+
+        db::read_playtime(conn, &name, player.as_deref(), since, now).await?
+    };
+
+    let list = rows.iter().fold(String::new(), |mut output, row| {
+        _ = writeln!(
+            output,
+            "{} - {} in {} session{} - peak score {} - last seen <t:{}:R>",
+            row.player_name,
+            format_duration(row.seconds),
+            row.sessions,
+            if row.sessions == 1 { "" } else { "s" },
+            row.peak_score,
+            row.last_seen,
+        );
+        output
+    });
+
+    let mut list = if list.is_empty() {
+        "Nobody played in this time".to_string()
+    } else {
+        list
+    };
+
+    // discord message limit is 2000
+    truncate_lines(&mut list, 1900);
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!(
+                "Players on `{}` since <t:{}:f>\n{}",
+                name, since, list
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub mod db {
+    use crate::Error;
+    use sqlx::SqliteConnection;
+
+    pub async fn open_session(
+        conn: &mut SqliteConnection,
+        server: &str,
+        player: &str,
+        time: i64,
+        score: i32,
+    ) -> Result<i64, Error> {
+        let res = sqlx::query!(
+            "INSERT INTO player_sessions (server_name, player_name, join_time, last_seen, peak_score) VALUES (?, ?, ?, ?, ?)",
+            server,
+            player,
+            time,
+            time,
+            score
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(res.last_insert_rowid())
+    }
+
+    pub async fn update_session(
+        conn: &mut SqliteConnection,
+        id: i64,
+        time: i64,
+        peak: i32,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            "UPDATE player_sessions SET last_seen = ?, peak_score = ? WHERE id = ?",
+            time,
+            peak,
+            id
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn close_session(
+        conn: &mut SqliteConnection,
+        id: i64,
+        time: i64,
+        peak: i32,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            "UPDATE player_sessions SET leave_time = ?, last_seen = ?, peak_score = ? WHERE id = ?",
+            time,
+            time,
+            peak,
+            id
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Sessions left open by a restart end when their player was last seen
+    pub async fn close_dangling_sessions(conn: &mut SqliteConnection) -> Result<(), Error> {
+        sqlx::query!("UPDATE player_sessions SET leave_time = last_seen WHERE leave_time IS NULL")
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    pub struct Playtime {
+        pub player_name: String,
+        pub sessions: i64,
+        pub seconds: i64,
+        pub peak_score: i64,
+        pub last_seen: i64,
+    }
+
+    /// Total playtime per player on `server` between `since` and `now`, open sessions count until `now`
+    pub async fn read_playtime(
+        conn: &mut SqliteConnection,
+        server: &str,
+        player: Option<&str>,
+        since: i64,
+        now: i64,
+    ) -> Result<Vec<Playtime>, Error> {
+        let rows = sqlx::query_as!(
+            Playtime,
+            r#"SELECT player_name,
+       COUNT(*) AS "sessions!: i64",
+       SUM(COALESCE(leave_time, ?) - MAX(join_time, ?)) AS "seconds!: i64",
+       MAX(peak_score) AS "peak_score!: i64",
+       MAX(COALESCE(leave_time, last_seen)) AS "last_seen!: i64"
+FROM player_sessions
+WHERE server_name = ?
+  AND COALESCE(leave_time, ?) >= ?
+  AND (? IS NULL OR player_name = ?)
+GROUP BY player_name
+ORDER BY 3 DESC"#,
+            now,
+            since,
+            server,
+            now,
+            since,
+            player,
+            player
+        )
+        .fetch_all(conn)
+        .await?;
+
+        Ok(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csgo_server::players::Players;
+    use csgo_server::testing::player;
+
+    fn diff(joined: &[&str], left: &[&str], players: &[(&str, i32)]) -> PlayerDiff {
+        PlayerDiff {
+            server: "meow".into(),
+            time: SystemTime::now(),
+            joined: joined.iter().map(|n| player(0, n, 0, 0.0)).collect(),
+            left: left.iter().map(|n| player(0, n, 0, 0.0)).collect(),
+            players: Players(
+                players
+                    .iter()
+                    .map(|(n, score)| player(0, n, *score, 0.0))
+                    .collect(),
+            ),
+            map: Some("dz_sirocco".into()),
+        }
+    }
+
+    /// Apply `ops` the way `apply` does without a database, handing out ids in order
+    fn apply(sessions: &mut Sessions, ops: Vec<Op>, time: i64) -> Vec<String> {
+        ops.into_iter()
+            .map(|op| match op {
+                Op::Open { name, score } => {
+                    let id = sessions.0.len() as i64 + 100;
+                    let line = format!("open {name}");
+                    sessions.opened("meow", name, id, score, time);
+                    line
+                }
+                Op::Update { id, peak } => format!("update {id} {peak}"),
+                Op::Close { id, peak } => format!("close {id} {peak}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn truncate_multibyte_names() {
+        // every `ж` is two bytes, so byte 1900 falls inside one
+        let line = format!("a{}\n", "ж".repeat(100));
+        let mut list = line.repeat(10);
+        assert!(!list.is_char_boundary(1900));
+
+        truncate_lines(&mut list, 1900);
+        assert_eq!(list, format!("{}\n...", line.repeat(9).trim_end()));
+
+        let mut short = line.clone();
+        truncate_lines(&mut short, 1900);
+        assert_eq!(short, line);
+    }
+
+    fn poll(sessions: &mut Sessions, diff: PlayerDiff, time: i64) -> Vec<String> {
+        let ops = sessions.diff_ops(&diff, time);
+        apply(sessions, ops, time)
+    }
+
+    #[test]
+    fn join_and_leave() {
+        let mut sessions = Sessions::default();
+
+        let ops = poll(&mut sessions, diff(&["cat"], &[], &[("cat", 0)]), 0);
+        assert_eq!(ops, ["open cat"]);
+
+        let ops = poll(&mut sessions, diff(&[], &[], &[("cat", 5)]), 10);
+        assert!(ops.is_empty());
+
+        let ops = poll(&mut sessions, diff(&[], &["cat"], &[]), 20);
+        assert_eq!(ops, ["close 100 5"]);
+        assert!(sessions.0.is_empty());
+    }
+
+    #[test]
+    fn missed_leave() {
+        let mut sessions = Sessions::default();
+
+        poll(&mut sessions, diff(&[], &[], &[("cat", 1), ("dog", 2)]), 0);
+
+        // the poll where dog left was lost to `Lagged`, but dog isn't on anymore
+        let ops = poll(&mut sessions, diff(&[], &[], &[("cat", 1)]), 10);
+        assert_eq!(ops, ["close 101 2"]);
+        assert!(sessions.0.contains_key(&("meow".into(), "cat".into())));
+    }
+
+    #[test]
+    fn flush_throttling() {
+        let mut sessions = Sessions::default();
+
+        poll(&mut sessions, diff(&["cat"], &[], &[("cat", 0)]), 0);
+
+        let ops = poll(
+            &mut sessions,
+            diff(&[], &[], &[("cat", 3)]),
+            FLUSH_INTERVAL - 1,
+        );
+        assert!(ops.is_empty());
+
+        let ops = poll(&mut sessions, diff(&[], &[], &[("cat", 2)]), FLUSH_INTERVAL);
+        assert_eq!(ops, ["update 100 3"]);
+
+        let ops = poll(
+            &mut sessions,
+            diff(&[], &[], &[("cat", 2)]),
+            FLUSH_INTERVAL + 1,
+        );
+        assert!(ops.is_empty());
+    }
+}
@@ -11,6 +11,8 @@ pub struct Settings {
     pub external_redirector_address: Option<String>,
     pub activity_server_identifier: Option<String>,
     pub activity_server_max_players: Option<i64>,
+    /// `None` for Valve's master server
+    pub master_server_address: Option<String>,
 }
 impl TypeMapKey for Settings {
     type Value = Settings;
@@ -23,6 +25,7 @@ impl Default for Settings {
             external_redirector_address: Some("https://dz.kotiboksi.xyz".to_string()),
             activity_server_identifier: Some("meow".into()),
             activity_server_max_players: Some(16),
+            master_server_address: None,
         }
     }
 }
@@ -66,6 +69,43 @@ pub async fn set_external_redirector(
     Ok(())
 }
 
+#[poise::command(slash_command, check = "privilege_check")]
+pub async fn set_master_server(
+    ctx: Context<'_>,
+    #[description = "Master server address for /discover, leave empty for Valve's"] addr: Option<
+        String,
+    >,
+) -> Result<(), Error> {
+    let mut data = ctx.serenity_context().data.write().await;
+
+    let settings = data
+        .get_mut::<Settings>()
+        .ok_or("DataError: Unable to get settings")?;
+    settings.master_server_address = addr.filter(|a| !a.is_empty());
+
+    let settings = settings.to_owned();
+
+    let conn = data
+        .get_mut::<DbConnection>()
+        .ok_or("DataError: Unable to get database connection")?;
+    store_settings(settings.clone(), conn).await?;
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!(
+                "/discover now asks {}",
+                settings
+                    .master_server_address
+                    .as_deref()
+                    .unwrap_or("Valve's master server")
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
 pub mod db {
     use super::Settings;
     use crate::Error;
@@ -96,15 +136,18 @@ pub mod db {
  id,
  external_redirector_address,
  activity_server_identifier,
- activity_server_max_players
-) VALUES (1, ?, ?, ?)
+ activity_server_max_players,
+ master_server_address
+) VALUES (1, ?, ?, ?, ?)
 ON CONFLICT(id) DO UPDATE
 SET external_redirector_address = excluded.external_redirector_address,
     activity_server_identifier = excluded.activity_server_identifier,
-    activity_server_max_players = excluded.activity_server_max_players",
+    activity_server_max_players = excluded.activity_server_max_players,
+    master_server_address = excluded.master_server_address",
             settings.external_redirector_address,
             settings.activity_server_identifier,
             settings.activity_server_max_players,
+            settings.master_server_address,
         )
         .execute(conn)
         .await?;
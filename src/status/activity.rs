@@ -1,10 +1,10 @@
 use crate::Error;
-use crate::ServerSocket;
+use crate::poller::Pollers;
 use crate::server_info::Info;
 use crate::servers::Servers;
 use crate::settings::Settings;
 
-use crate::server_info::get_server_info;
+use crate::poller::get_server_info;
 use ::serenity::prelude::TypeMap;
 use poise::serenity_prelude as serenity;
 use std::sync::Arc;
@@ -23,14 +23,14 @@ async fn bot_status(data: RwLockReadGuard<'_, TypeMap>) -> Result<String, Error>
         None => &"meow".to_string(),
     };
 
-    let socks = data
-        .get::<ServerSocket>()
-        .ok_or("DataError: Unable to get sockets")?;
+    let pollers = data
+        .get::<Pollers>()
+        .ok_or("DataError: Unable to get pollers")?;
     let servers = data
         .get::<Servers>()
         .ok_or("DataError: Unable to get servers")?;
 
-    let info = get_server_info(socks, ident).await?;
+    let info = get_server_info(pollers, ident).await?;
     let server = servers
         .get(ident)
         .ok_or(format!("ServerError: Unable to get server {}", ident))?;
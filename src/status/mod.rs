@@ -7,16 +7,17 @@ use std::time::Duration;
 use std::time::UNIX_EPOCH;
 
 use crate::serenity::CreateActionRow;
-use crate::server_info::get_server_info;
+use crate::server_info::DownReason;
 use crate::server_info::ServerDown;
 use crate::server_info::ServerUp;
 use crate::servers::Server;
 use crate::servers::Servers;
 use crate::settings::Settings;
-use crate::socket::{ServerSocket, ServerSocketValue};
+use crate::poller::{get_server_info, Pollers, PollersValue};
 use crate::{Context, Error};
 use ::serenity::all::{Colour, CreateAttachment, CreateEmbed, CreateEmbedFooter};
 use csgo_server::players::Player;
+use csgo_server::rules::Rules;
 use poise::serenity_prelude as serenity;
 use poise::CreateReply;
 use urlencoding::encode;
@@ -25,11 +26,11 @@ use serenity::all::CreateButton;
 
 pub async fn make_status_message(
     external_redirector: Option<String>,
-    socks: &ServerSocketValue,
+    pollers: &PollersValue,
     name: &String, // not really required, but servers are stored as a hashmap so this will always be there anyway
     server: &Server,
 ) -> Result<(CreateEmbed, Vec<CreateActionRow>, Vec<CreateAttachment>), Error> {
-    let info = get_server_info(socks, name).await?;
+    let info = get_server_info(pollers, name).await?;
 
     let mut buttons: Vec<CreateButton> = vec![];
     let mut attachments = vec![CreateAttachment::path("static/respawnwcat.png").await?];
@@ -79,7 +80,7 @@ pub async fn make_status_message(
                 r#"
 `{} - {} players online`
 Time since map change `{:0>2}:{:0>2}`
-
+{}
 Players
 ```
 {}
@@ -94,6 +95,10 @@ If there are 16 or more players, you **must** connect through the console.
                 // max,
                 (info.elapsed.as_secs() / 60) % 60,
                 info.elapsed.as_secs() % 60,
+                match info.rules.as_ref().and_then(format_rules) {
+                    Some(rules) => format!("\nSettings\n```\n{}\n```\n", rules),
+                    None => "".into(),
+                },
                 // discord breaks formatting of codeblocks if it's empty
                 if players.len() > 0 {
                     format_players(players, &info.elapsed)
@@ -123,17 +128,28 @@ If there are 16 or more players, you **must** connect through the console.
             }
         }
         Info::ServerDown(down) => {
-            embed = embed.title("Server down");
-            embed = embed.color(Colour::DARK_RED);
+	    let since = down.since.duration_since(UNIX_EPOCH)?.as_secs();
 
+            embed = embed.color(Colour::DARK_RED);
 	    embed = embed.thumbnail("attachment://respawnwcat.png");
-            embed = embed.description(format!(
-                r#"
+
+            embed = match down.reason {
+                DownReason::Unreachable => embed.title("Server down").description(format!(
+                    r#"
 Server `{}` has been down since <t:{}:R>
 "#,
-                name,
-		down.since.duration_since(UNIX_EPOCH)?.as_secs()
-            ))
+                    name, since
+                )),
+                DownReason::MalformedReply(error) => embed
+                    .title("Server not responding correctly")
+                    .description(format!(
+                        r#"
+Server `{}` has been sending invalid replies since <t:{}:R>
+```{}```
+"#,
+                        name, since, error
+                    )),
+            };
         }
     }
 
@@ -159,9 +175,9 @@ pub async fn status(
         .external_redirector_address
         .clone()
         .unwrap_or_default();
-    let socks = data
-        .get::<ServerSocket>()
-        .ok_or("DataError: Unable to get sockets")?;
+    let pollers = data
+        .get::<Pollers>()
+        .ok_or("DataError: Unable to get pollers")?;
 
     let server = data
         .get::<Servers>()
@@ -170,7 +186,7 @@ pub async fn status(
         .ok_or(format!("ServerError: Unable to get server {}", &name))?;
 
     let (embed, action, attachments) =
-        make_status_message(Some(redirect), socks, &name, server).await?;
+        make_status_message(Some(redirect), pollers, &name, server).await?;
 
     let mut message = CreateReply::default()
         .embed(embed)
@@ -186,6 +202,24 @@ pub async fn status(
     Ok(())
 }
 
+/// Cvars shown in the status embed, everything starting with `sv_dz_` is shown as well
+const DISPLAYED_RULES: &[&str] = &["mp_roundtime", "mp_timelimit", "sv_allowupload"];
+
+fn format_rules(rules: &Rules) -> Option<String> {
+    let lines = rules
+        .0
+        .iter()
+        .filter(|(name, _)| DISPLAYED_RULES.contains(&&***name) || name.starts_with("sv_dz_"))
+        .map(|(name, value)| format!("{name} {value}"))
+        .collect::<Vec<_>>();
+
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n"))
+    }
+}
+
 // TODO per server warmup time
 const WARMUP: f32 = 100.0;
 fn playing_game(player: &Player, duration: &Duration) -> bool {
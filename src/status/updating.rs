@@ -13,7 +13,7 @@ use sqlx::SqliteConnection;
 
 use crate::settings::Settings;
 use crate::{db::DbConnection, Context};
-use crate::{socket::ServerSocket, status::make_status_message, Error};
+use crate::{poller::Pollers, status::make_status_message, Error};
 
 pub struct UpdatingStatusMessages;
 impl TypeMapKey for UpdatingStatusMessages {
@@ -44,12 +44,12 @@ async fn update_status_message(
         .get(&name)
         .ok_or(format!("ServerError: Unable to get server {}", &name))?;
 
-    let socks = data
-        .get::<ServerSocket>()
-        .ok_or("DataError: Unable to get sockets")?;
+    let pollers = data
+        .get::<Pollers>()
+        .ok_or("DataError: Unable to get pollers")?;
 
     let (embed, action, attachments) =
-        make_status_message(redirector, socks, &name, server).await?;
+        make_status_message(redirector, pollers, &name, server).await?;
 
     let mut message = EditMessage::new()
         .content("")
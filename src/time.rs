@@ -0,0 +1,34 @@
+use crate::Error;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// Seconds since the unix epoch, the way the database stores times
+pub fn unix(time: SystemTime) -> Result<i64, Error> {
+    Ok(time.duration_since(UNIX_EPOCH)?.as_secs() as i64)
+}
+
+pub fn now() -> Result<i64, Error> {
+    unix(SystemTime::now())
+}
+
+/// `1h 05m`, or just `5m` under an hour
+pub fn format_duration(secs: i64) -> String {
+    if secs >= 3600 {
+        format!("{}h {:0>2}m", secs / 3600, (secs / 60) % 60)
+    } else {
+        format!("{}m", secs / 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(format_duration(59), "0m");
+        assert_eq!(format_duration(45 * 60), "45m");
+        assert_eq!(format_duration(3600), "1h 00m");
+        assert_eq!(format_duration(26 * 3600 + 5 * 60), "26h 05m");
+    }
+}
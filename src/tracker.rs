@@ -0,0 +1,80 @@
+//! Loops turning the `PLAYER_DIFFS` of every poll into database rows, see `sessions` and `maps`
+
+use crate::db::DbConnection;
+use crate::server_info::PlayerDiff;
+use crate::server_info::PLAYER_DIFFS;
+use crate::time::unix;
+use crate::Error;
+use poise::serenity_prelude as serenity;
+use sqlx::SqliteConnection;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Running statistics of open rows are written at most this often
+pub const FLUSH_INTERVAL: i64 = 60;
+
+/// State kept between polls, like which rows are still open
+pub trait Tracker: Send + 'static {
+    /// A single database write
+    type Op: Send;
+
+    /// Used in error messages
+    const NAME: &'static str;
+
+    /// Work out what has to be written for this poll, without touching the database
+    fn diff_ops(&mut self, diff: &PlayerDiff, time: i64) -> Vec<Self::Op>;
+
+    /// Write `op`, remembering any row it opened
+    fn apply(
+        &mut self,
+        conn: &mut SqliteConnection,
+        diff: &PlayerDiff,
+        time: i64,
+        op: Self::Op,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+}
+
+async fn track<T: Tracker>(
+    ctx: &serenity::Context,
+    tracker: &mut T,
+    diff: PlayerDiff,
+) -> Result<(), Error> {
+    let time = unix(diff.time)?;
+    let ops = tracker.diff_ops(&diff, time);
+
+    if ops.is_empty() {
+        return Ok(());
+    }
+
+    let mut data = ctx.data.write().await;
+    let conn = data
+        .get_mut::<DbConnection>()
+        .ok_or("DataError: Unable to get database connection")?;
+
+    for op in ops {
+        tracker.apply(conn, &diff, time, op).await?;
+    }
+
+    Ok(())
+}
+
+/// Feed every poll to `tracker`.
+///
+/// Runs once for the whole process, aborting it halfway through `track` or starting over with
+/// an empty tracker would leave rows open twice. Rows the last run left open are closed in `main`
+pub async fn tracker_loop<T: Tracker>(ctx: Arc<serenity::Context>, mut tracker: T) {
+    let mut diffs = PLAYER_DIFFS.subscribe();
+
+    loop {
+        match diffs.recv().await {
+            Ok(diff) => {
+                if let Err(e) = track(&ctx, &mut tracker, diff).await {
+                    eprintln!("Error with {} {e:?}", T::NAME);
+                }
+            }
+            Err(RecvError::Lagged(n)) => eprintln!("{} missed {n} polls", T::NAME),
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
@@ -0,0 +1,489 @@
+use crate::db::DbConnection;
+use crate::server_info::PlayerDiff;
+use crate::server_info::PLAYER_DIFFS;
+use crate::servers::Servers;
+use crate::time::unix;
+use crate::{Context, Error};
+use ::serenity::all::CreateMessage;
+use poise::serenity_prelude as serenity;
+use poise::serenity_prelude::prelude::TypeMapKey;
+use poise::CreateReply;
+use regex::Regex;
+use regex::RegexBuilder;
+use serenity::UserId;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Watches a single user can have
+const MAX_WATCHES: usize = 10;
+const MAX_PATTERN_LENGTH: usize = 100;
+/// Someone reconnecting doesn't get announced again within this many seconds
+const REPEAT_DELAY: i64 = 600;
+
+/// A user waiting for a player to join
+#[derive(Debug, Clone)]
+pub struct Watch {
+    pub id: i64,
+    pub user: UserId,
+    pub pattern: String,
+    pub regex: bool,
+    /// Every server if `None`
+    pub server: Option<String>,
+    matcher: Regex,
+}
+
+impl Watch {
+    pub fn new(
+        id: i64,
+        user: UserId,
+        pattern: String,
+        regex: bool,
+        server: Option<String>,
+    ) -> Result<Self, Error> {
+        let matcher = compile(&pattern, regex)?;
+
+        Ok(Watch {
+            id,
+            user,
+            pattern,
+            regex,
+            server,
+            matcher,
+        })
+    }
+
+    fn matches(&self, server: &str, player: &str) -> bool {
+        self.server.as_ref().is_none_or(|s| s == server) && self.matcher.is_match(player)
+    }
+}
+
+/// Globs have to match the whole name, a regex can match anywhere, both ignore case
+fn compile(pattern: &str, regex: bool) -> Result<Regex, Error> {
+    let source = if regex {
+        pattern.to_string()
+    } else {
+        let mut source = "^".to_string();
+        for c in pattern.chars() {
+            match c {
+                '*' => source.push_str(".*"),
+                '?' => source.push('.'),
+                c => source.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+            }
+        }
+        source.push('$');
+        source
+    };
+
+    Ok(RegexBuilder::new(&source)
+        .case_insensitive(true)
+        .size_limit(1 << 16)
+        .build()
+        .map_err(|e| format!("Invalid pattern: {e}"))?)
+}
+
+pub struct Watches;
+pub type WatchesValue = Vec<Watch>;
+impl TypeMapKey for Watches {
+    type Value = WatchesValue;
+}
+
+/// What has been announced recently, so flaky connections don't spam anyone
+#[derive(Default)]
+struct Announced {
+    /// Servers whose first poll has been seen, everyone on it "joins" then
+    baseline: HashSet<String>,
+    // (user, server, player), time
+    recent: HashMap<(UserId, String, Box<str>), i64>,
+}
+
+/// Players each user should hear about for this poll
+fn matches(
+    watches: &WatchesValue,
+    announced: &mut Announced,
+    diff: &PlayerDiff,
+    now: i64,
+) -> HashMap<UserId, Vec<Box<str>>> {
+    let mut found: HashMap<UserId, Vec<Box<str>>> = HashMap::new();
+
+    announced
+        .recent
+        .retain(|_, time| now - *time < REPEAT_DELAY);
+
+    for player in &diff.joined {
+        for watch in watches
+            .iter()
+            .filter(|w| w.matches(&diff.server, &player.name))
+        {
+            let key = (watch.user, diff.server.clone(), player.name.clone());
+            if announced.recent.contains_key(&key) {
+                continue;
+            }
+            announced.recent.insert(key, now);
+
+            found
+                .entry(watch.user)
+                .or_default()
+                .push(player.name.clone());
+        }
+    }
+
+    found
+}
+
+async fn announce(
+    ctx: &serenity::Context,
+    announced: &mut Announced,
+    diff: PlayerDiff,
+) -> Result<(), Error> {
+    if announced.baseline.insert(diff.server.clone()) || diff.joined.is_empty() {
+        return Ok(());
+    }
+
+    let found = {
+        let data = ctx.data.read().await;
+        let watches = data
+            .get::<Watches>()
+            .ok_or("DataError: Unable to get watches")?;
+
+        matches(watches, announced, &diff, unix(diff.time)?)
+    };
+
+    for (user, players) in found {
+        let names = players
+            .iter()
+            .map(|p| format!("`{p}`"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let message = CreateMessage::new().content(format!("{} joined `{}`", names, diff.server));
+
+        // closed DMs shouldn't stop everyone else's
+        if let Err(e) = user.direct_message(ctx, message).await {
+            eprintln!("Unable to tell {user} about {names}: {e}");
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn watch_loop(ctx: Arc<serenity::Context>) {
+    let mut diffs = PLAYER_DIFFS.subscribe();
+    let mut announced = Announced::default();
+
+    loop {
+        match diffs.recv().await {
+            Ok(diff) => {
+                if let Err(e) = announce(&ctx, &mut announced, diff).await {
+                    eprintln!("Error with player watches {e:?}");
+                }
+            }
+            Err(RecvError::Lagged(n)) => eprintln!("Player watches missed {n} polls"),
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+fn watch_help() -> String {
+    format!(
+        "Get a DM when someone whose name matches joins a server.
+Patterns are globs by default, `*` matches anything and `?` any single character, \
+set regex to use a regular expression instead. Case is ignored either way.
+You can have up to {MAX_WATCHES} watches."
+    )
+}
+
+#[poise::command(slash_command, help_text_fn = "watch_help", category = "Notifications")]
+pub async fn watch(
+    ctx: Context<'_>,
+    #[description = "Player name, or a pattern like *meow*"] pattern: String,
+    #[description = "Server identifier, defaults to all"] name: Option<String>,
+    #[description = "Treat the pattern as a regular expression"] regex: Option<bool>,
+) -> Result<(), Error> {
+    if pattern.len() > MAX_PATTERN_LENGTH {
+        return Err(format!("Patterns can be at most {MAX_PATTERN_LENGTH} characters").into());
+    }
+
+    let user = ctx.author().id;
+    let mut watch = Watch::new(0, user, pattern, regex.unwrap_or(false), name)?;
+
+    let mut data = ctx.serenity_context().data.write().await;
+
+    if let Some(name) = &watch.server
+        && !data
+            .get::<Servers>()
+            .ok_or("DataError: Unable to get servers")?
+            .contains_key(name)
+    {
+        return Err(format!("Server {name} doesn't exist").into());
+    }
+
+    let watches = data
+        .get::<Watches>()
+        .ok_or("DataError: Unable to get watches")?;
+    let own = watches.iter().filter(|w| w.user == user).count();
+
+    if own >= MAX_WATCHES {
+        return Err(
+            format!("You already have {MAX_WATCHES} watches, remove some with /unwatch").into(),
+        );
+    }
+    if watches
+        .iter()
+        .any(|w| w.user == user && w.pattern == watch.pattern && w.server == watch.server)
+    {
+        return Err(format!("You're already watching for `{}`", watch.pattern).into());
+    }
+
+    let conn = data
+        .get_mut::<DbConnection>()
+        .ok_or("DataError: Unable to get database connection")?;
+    watch.id = db::insert_watch(conn, &watch).await?;
+
+    let content = format!(
+        "I'll DM you when {} joins {} ({}/{MAX_WATCHES} watches)",
+        describe(&watch),
+        watch
+            .server
+            .as_ref()
+            .map(|s| format!("`{s}`"))
+            .unwrap_or("any server".into()),
+        own + 1
+    );
+
+    data.get_mut::<Watches>()
+        .ok_or("DataError: Unable to get watches")?
+        .push(watch);
+
+    ctx.send(CreateReply::default().content(content).ephemeral(true))
+        .await?;
+
+    Ok(())
+}
+
+fn unwatch_help() -> String {
+    "Stop watching for a pattern, or list your watches if none is given.".into()
+}
+
+#[poise::command(
+    slash_command,
+    help_text_fn = "unwatch_help",
+    category = "Notifications"
+)]
+pub async fn unwatch(
+    ctx: Context<'_>,
+    #[description = "Pattern to stop watching for"] pattern: Option<String>,
+) -> Result<(), Error> {
+    let user = ctx.author().id;
+    let mut data = ctx.serenity_context().data.write().await;
+
+    let watches = data
+        .get_mut::<Watches>()
+        .ok_or("DataError: Unable to get watches")?;
+
+    let Some(pattern) = pattern else {
+        let list =
+            watches
+                .iter()
+                .filter(|w| w.user == user)
+                .fold(String::new(), |mut output, watch| {
+                    _ = writeln!(
+                        output,
+                        "{} on {}",
+                        describe(watch),
+                        watch.server.as_deref().unwrap_or("any server")
+                    );
+                    output
+                });
+
+        ctx.send(
+            CreateReply::default()
+                .content(if list.is_empty() {
+                    "You aren't watching for anyone".to_string()
+                } else {
+                    list
+                })
+                .ephemeral(true),
+        )
+        .await?;
+
+        return Ok(());
+    };
+
+    let removed: Vec<i64> = watches
+        .iter()
+        .filter(|w| w.user == user && w.pattern == pattern)
+        .map(|w| w.id)
+        .collect();
+
+    if removed.is_empty() {
+        return Err(format!("You aren't watching for `{pattern}`").into());
+    }
+    watches.retain(|w| !removed.contains(&w.id));
+
+    let conn = data
+        .get_mut::<DbConnection>()
+        .ok_or("DataError: Unable to get database connection")?;
+    for id in removed {
+        db::remove_watch(conn, id).await?;
+    }
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!("Stopped watching for `{pattern}`"))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+fn describe(watch: &Watch) -> String {
+    format!(
+        "`{}`{}",
+        watch.pattern,
+        if watch.regex { " (regex)" } else { "" }
+    )
+}
+
+pub mod db {
+    use super::{Watch, WatchesValue};
+    use crate::Error;
+    use poise::serenity_prelude::UserId;
+    use sqlx::SqliteConnection;
+
+    pub async fn read_watches(conn: &mut SqliteConnection) -> Result<WatchesValue, Error> {
+        struct Fetch {
+            id: i64,
+            user_id: String,
+            pattern: String,
+            regex: bool,
+            server_name: Option<String>,
+        }
+
+        let watches = sqlx::query_as!(
+            Fetch,
+            "SELECT id, user_id, pattern, regex, server_name FROM player_watches"
+        )
+        .fetch_all(conn)
+        .await?;
+
+        Ok(watches
+            .into_iter()
+            .filter_map(|v| {
+                let user = UserId::new(v.user_id.parse().ok()?);
+                Watch::new(v.id, user, v.pattern, v.regex, v.server_name).ok()
+            })
+            .collect())
+    }
+
+    pub async fn insert_watch(conn: &mut SqliteConnection, watch: &Watch) -> Result<i64, Error> {
+        let user = watch.user.to_string();
+
+        let res = sqlx::query!(
+            "INSERT INTO player_watches (user_id, pattern, regex, server_name) VALUES (?, ?, ?, ?)",
+            user,
+            watch.pattern,
+            watch.regex,
+            watch.server
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(res.last_insert_rowid())
+    }
+
+    pub async fn remove_watch(conn: &mut SqliteConnection, id: i64) -> Result<(), Error> {
+        sqlx::query!("DELETE FROM player_watches WHERE id = ?", id)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_server_watches(
+        conn: &mut SqliteConnection,
+        server: &str,
+    ) -> Result<(), Error> {
+        sqlx::query!("DELETE FROM player_watches WHERE server_name = ?", server)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csgo_server::players::Player;
+    use csgo_server::players::Players;
+    use std::time::SystemTime;
+
+    fn watch(pattern: &str, regex: bool, server: Option<&str>) -> Watch {
+        Watch::new(
+            1,
+            UserId::new(1),
+            pattern.into(),
+            regex,
+            server.map(String::from),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn globs() {
+        assert!(watch("meow", false, None).matches("meow", "MEOW"));
+        assert!(!watch("meow", false, None).matches("meow", "meowmeow"));
+        assert!(watch("*meow*", false, None).matches("meow", "big meow cat"));
+        assert!(watch("m??w", false, None).matches("meow", "miaw"));
+        // regex syntax in a glob is taken literally
+        assert!(watch("a.b", false, None).matches("meow", "a.b"));
+        assert!(!watch("a.b", false, None).matches("meow", "axb"));
+    }
+
+    #[test]
+    fn regexes() {
+        assert!(watch(r"^\[cat\]", true, None).matches("meow", "[CAT] tom"));
+        assert!(!watch(r"^\[cat\]", true, None).matches("meow", "tom [cat]"));
+        assert!(Watch::new(1, UserId::new(1), "(".into(), true, None).is_err());
+    }
+
+    #[test]
+    fn server_filter() {
+        let w = watch("tom", false, Some("meow"));
+
+        assert!(w.matches("meow", "tom"));
+        assert!(!w.matches("woof", "tom"));
+    }
+
+    #[test]
+    fn reconnects_are_announced_once() {
+        let player = Player {
+            index: 0,
+            name: "tom".into(),
+            raw_name: b"tom".as_slice().into(),
+            score: 0,
+            duration: 0.0,
+            the_ship: None,
+        };
+        let diff = PlayerDiff {
+            server: "meow".into(),
+            time: SystemTime::now(),
+            joined: vec![player.clone()],
+            left: vec![],
+            players: Players(vec![player]),
+            map: None,
+        };
+        let watches = vec![watch("tom", false, None)];
+        let mut announced = Announced::default();
+
+        assert_eq!(matches(&watches, &mut announced, &diff, 0).len(), 1);
+        assert!(matches(&watches, &mut announced, &diff, 10).is_empty());
+        assert_eq!(
+            matches(&watches, &mut announced, &diff, REPEAT_DELAY + 1).len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,748 @@
+use crate::db::DbConnection;
+use crate::privilege_check;
+use crate::servers::Servers;
+use crate::{Context, Error};
+use once_cell::sync::Lazy;
+use poise::serenity_prelude::prelude::TypeMapKey;
+use poise::CreateReply;
+use reqwest::StatusCode;
+use reqwest::Url;
+use serde::Serialize;
+use serde_json::json;
+use std::fmt::Write;
+use std::str::FromStr;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+use tokio::time;
+
+/// Something the down detector noticed about a server
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Down { since: u64 },
+    Recovered,
+    MapChange { from: String, to: String },
+    Full { players: usize, max_players: i64 },
+}
+
+/// Names of the events, in the order of `Event`
+const EVENTS: [&str; 4] = ["down", "recovered", "map_change", "full"];
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Down { .. } => EVENTS[0],
+            Event::Recovered => EVENTS[1],
+            Event::MapChange { .. } => EVENTS[2],
+            Event::Full { .. } => EVENTS[3],
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            Event::Down { .. } => "Server down",
+            Event::Recovered => "Server back up",
+            Event::MapChange { .. } => "Map changed",
+            Event::Full { .. } => "Server full",
+        }
+    }
+
+    /// Plain text, the sinks don't agree on any markup
+    fn describe(&self, server: &str) -> String {
+        match self {
+            Event::Down { .. } => format!("{server} went down"),
+            Event::Recovered => format!("{server} is back up"),
+            Event::MapChange { from, to } => format!("{server} changed map from {from} to {to}"),
+            Event::Full {
+                players,
+                max_players,
+            } => format!("{server} is full ({players}/{max_players})"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, poise::ChoiceParameter)]
+pub enum Sink {
+    #[name = "Discord webhook"]
+    Discord,
+    #[name = "JSON POST"]
+    Json,
+    #[name = "ntfy"]
+    Ntfy,
+}
+
+impl Sink {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Sink::Discord => "discord",
+            Sink::Json => "json",
+            Sink::Ntfy => "ntfy",
+        }
+    }
+}
+
+impl FromStr for Sink {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "discord" => Ok(Sink::Discord),
+            "json" => Ok(Sink::Json),
+            "ntfy" => Ok(Sink::Ntfy),
+            _ => Err(format!("WebhookError: Unknown sink {s}").into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    pub id: i64,
+    pub server: String,
+    pub sink: Sink,
+    pub url: Url,
+    pub events: Vec<String>,
+}
+
+impl Webhook {
+    pub fn wants(&self, event: &Event) -> bool {
+        self.events.iter().any(|e| e == event.name())
+    }
+}
+
+pub struct Webhooks;
+pub type WebhooksValue = Vec<Webhook>;
+impl TypeMapKey for Webhooks {
+    type Value = WebhooksValue;
+}
+
+/// How often a delivery is attempted and how long to wait before the first retry, the wait
+/// doubles after every attempt
+pub struct Backoff {
+    pub attempts: u32,
+    pub initial: Duration,
+}
+
+const BACKOFF: Backoff = Backoff {
+    attempts: 4,
+    initial: Duration::from_secs(2),
+};
+
+/// Longest `Retry-After` we honour, a rate limit asking for more than this is treated as a failure
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+static CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("Unable to build http client")
+});
+
+#[derive(Serialize)]
+struct JsonPayload<'a> {
+    server: &'a str,
+    time: u64,
+    message: String,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+fn request(
+    client: &reqwest::Client,
+    hook: &Webhook,
+    event: &Event,
+) -> Result<reqwest::RequestBuilder, Error> {
+    let message = event.describe(&hook.server);
+    let post = client.post(hook.url.clone());
+
+    Ok(match hook.sink {
+        Sink::Discord => post.json(&json!({ "content": message })),
+        Sink::Json => post.json(&JsonPayload {
+            server: &hook.server,
+            time: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            message,
+            event,
+        }),
+        Sink::Ntfy => post
+            .header("Title", format!("{}: {}", hook.server, event.title()))
+            .header("Tags", event.name())
+            .body(message),
+    })
+}
+
+/// Send `event` to `hook`, retrying network errors, rate limits and server errors. A rate limit
+/// is waited out for as long as its `Retry-After` asks
+pub async fn deliver(
+    client: &reqwest::Client,
+    hook: &Webhook,
+    event: &Event,
+    backoff: &Backoff,
+) -> Result<(), Error> {
+    let mut delay = backoff.initial;
+    let mut attempt = 1;
+
+    loop {
+        let mut wait = delay;
+
+        let error = match request(client, hook, event)?.send().await {
+            Ok(res) if res.status().is_success() => return Ok(()),
+            Ok(res) if res.status() == StatusCode::TOO_MANY_REQUESTS => {
+                match retry_after(&res) {
+                    Some(after) if after > MAX_RETRY_AFTER => {
+                        return Err(format!(
+                            "WebhookError: Webhook {} is rate limited for {}s",
+                            hook.id,
+                            after.as_secs()
+                        )
+                        .into());
+                    }
+                    Some(after) => wait = wait.max(after),
+                    None => (),
+                }
+
+                format!("answered {}", res.status())
+            }
+            Ok(res) if res.status().is_server_error() => format!("answered {}", res.status()),
+            // anything else won't get better by asking again
+            Ok(res) => {
+                return Err(format!(
+                    "WebhookError: Webhook {} answered {}",
+                    hook.id,
+                    res.status()
+                )
+                .into());
+            }
+            // the url of a discord webhook holds its token
+            Err(e) => e.without_url().to_string(),
+        };
+
+        if attempt >= backoff.attempts {
+            return Err(format!(
+                "WebhookError: Webhook {} failed {attempt} times, last {error}",
+                hook.id
+            )
+            .into());
+        }
+
+        time::sleep(wait).await;
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+/// How long a rate limited webhook asks us to wait, only the delay in seconds form is understood
+fn retry_after(res: &reqwest::Response) -> Option<Duration> {
+    let secs = res
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<f64>()
+        .ok()?;
+
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+/// Deliver `event` to every webhook of `server` that wants it, in the background
+pub fn dispatch(hooks: &WebhooksValue, server: &str, event: &Event) {
+    for hook in hooks
+        .iter()
+        .filter(|h| h.server == server && h.wants(event))
+    {
+        let hook = hook.clone();
+        let event = event.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = deliver(&CLIENT, &hook, &event, &BACKOFF).await {
+                eprintln!("Unable to deliver {} of {}: {e}", event.name(), hook.server);
+            }
+        });
+    }
+}
+
+fn parse_events(events: Option<String>) -> Result<Vec<String>, Error> {
+    let Some(events) = events else {
+        return Ok(EVENTS.iter().map(|e| e.to_string()).collect());
+    };
+
+    let events = events
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|e| !e.is_empty())
+        .map(|e| match EVENTS.contains(&e) {
+            true => Ok(e.to_string()),
+            false => Err(format!(
+                "Unknown event {e}, pick from {}",
+                EVENTS.join(", ")
+            )),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if events.is_empty() {
+        return Err("A webhook needs at least one event".into());
+    }
+
+    Ok(events)
+}
+
+fn webhooks_help() -> String {
+    format!(
+        "Send server events to outgoing webhooks, for those who don't sit in Discord.
+Events are {}, failed deliveries are retried a few times.
+Requires admin privileges.",
+        EVENTS.join(", ")
+    )
+}
+
+#[poise::command(
+    slash_command,
+    subcommands("add", "remove", "list"),
+    check = "privilege_check",
+    category = "Webhooks",
+    help_text_fn = "webhooks_help"
+)]
+pub async fn webhooks(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Send events of a server to a webhook
+#[poise::command(slash_command, check = "privilege_check", category = "Webhooks")]
+async fn add(
+    ctx: Context<'_>,
+    #[description = "Server identifier"] name: String,
+    #[description = "What the url expects"] sink: Sink,
+    #[description = "Where to send events"] url: String,
+    #[description = "Events to send, separated by spaces, defaults to all"] events: Option<String>,
+) -> Result<(), Error> {
+    let url = Url::parse(&url).map_err(|e| format!("Invalid url: {e}"))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err("Webhooks have to be http or https".into());
+    }
+
+    let events = parse_events(events)?;
+
+    let mut data = ctx.serenity_context().data.write().await;
+
+    if !data
+        .get::<Servers>()
+        .ok_or("DataError: Unable to get servers")?
+        .contains_key(&name)
+    {
+        return Err(format!("Server {name} doesn't exist").into());
+    }
+
+    let conn = data
+        .get_mut::<DbConnection>()
+        .ok_or("DataError: Unable to get database connection")?;
+
+    let mut hook = Webhook {
+        id: 0,
+        server: name,
+        sink,
+        url,
+        events,
+    };
+    hook.id = db::insert_webhook(conn, &hook).await?;
+
+    let content = format!("Added webhook {}", describe(&hook));
+
+    data.get_mut::<Webhooks>()
+        .ok_or("DataError: Unable to get webhooks")?
+        .push(hook);
+
+    ctx.send(CreateReply::default().content(content).ephemeral(true))
+        .await?;
+
+    Ok(())
+}
+
+/// Stop sending events to a webhook
+#[poise::command(slash_command, check = "privilege_check", category = "Webhooks")]
+async fn remove(
+    ctx: Context<'_>,
+    #[description = "Webhook id, see /webhooks list"] id: i64,
+) -> Result<(), Error> {
+    let mut data = ctx.serenity_context().data.write().await;
+
+    let hooks = data
+        .get_mut::<Webhooks>()
+        .ok_or("DataError: Unable to get webhooks")?;
+
+    if !hooks.iter().any(|h| h.id == id) {
+        return Err(format!("Webhook {id} doesn't exist").into());
+    }
+    hooks.retain(|h| h.id != id);
+
+    let conn = data
+        .get_mut::<DbConnection>()
+        .ok_or("DataError: Unable to get database connection")?;
+    db::remove_webhook(conn, id).await?;
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!("Removed webhook {id}"))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// List webhooks, of one server or all of them
+#[poise::command(slash_command, check = "privilege_check", category = "Webhooks")]
+async fn list(
+    ctx: Context<'_>,
+    #[description = "Server identifier"] name: Option<String>,
+) -> Result<(), Error> {
+    let data = ctx.serenity_context().data.read().await;
+
+    let hooks = data
+        .get::<Webhooks>()
+        .ok_or("DataError: Unable to get webhooks")?;
+
+    let list = hooks
+        .iter()
+        .filter(|h| name.as_ref().is_none_or(|n| *n == h.server))
+        .fold(String::new(), |mut output, hook| {
+            _ = writeln!(output, "{}", describe(hook));
+            output
+        });
+
+    ctx.send(
+        CreateReply::default()
+            .content(if list.is_empty() {
+                "No webhooks configured".to_string()
+            } else {
+                list
+            })
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Webhook urls are secrets, so only the host is shown
+fn describe(hook: &Webhook) -> String {
+    format!(
+        "`{}` for `{}` - {} to {}, sends {}",
+        hook.id,
+        hook.server,
+        poise::ChoiceParameter::name(&hook.sink),
+        hook.url.host_str().unwrap_or("?"),
+        hook.events.join(", ")
+    )
+}
+
+pub mod db {
+    use super::{Webhook, WebhooksValue};
+    use crate::Error;
+    use reqwest::Url;
+    use sqlx::SqliteConnection;
+
+    pub async fn read_webhooks(conn: &mut SqliteConnection) -> Result<WebhooksValue, Error> {
+        struct Fetch {
+            id: i64,
+            server_name: String,
+            kind: String,
+            url: String,
+            events: String,
+        }
+
+        let hooks = sqlx::query_as!(
+            Fetch,
+            "SELECT id, server_name, kind, url, events FROM server_webhooks"
+        )
+        .fetch_all(conn)
+        .await?;
+
+        Ok(hooks
+            .into_iter()
+            .filter_map(|v| {
+                Some(Webhook {
+                    id: v.id,
+                    server: v.server_name,
+                    sink: v.kind.parse().ok()?,
+                    url: Url::parse(&v.url).ok()?,
+                    events: v.events.split_whitespace().map(String::from).collect(),
+                })
+            })
+            .collect())
+    }
+
+    pub async fn insert_webhook(conn: &mut SqliteConnection, hook: &Webhook) -> Result<i64, Error> {
+        let kind = hook.sink.as_str();
+        let url = hook.url.as_str();
+        let events = hook.events.join(" ");
+
+        let res = sqlx::query!(
+            "INSERT INTO server_webhooks (server_name, kind, url, events) VALUES (?, ?, ?, ?)",
+            hook.server,
+            kind,
+            url,
+            events
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(res.last_insert_rowid())
+    }
+
+    pub async fn remove_webhook(conn: &mut SqliteConnection, id: i64) -> Result<(), Error> {
+        sqlx::query!("DELETE FROM server_webhooks WHERE id = ?", id)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_server_webhooks(
+        conn: &mut SqliteConnection,
+        server: &str,
+    ) -> Result<(), Error> {
+        sqlx::query!("DELETE FROM server_webhooks WHERE server_name = ?", server)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::State;
+    use axum::http::HeaderMap;
+    use axum::routing::post;
+    use axum::Router;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use tokio::net::TcpListener;
+
+    /// A local http server answering with `statuses` in order, then 200
+    #[derive(Default)]
+    struct StandIn {
+        statuses: Mutex<Vec<u16>>,
+        requests: Mutex<Vec<(HeaderMap, String)>>,
+        /// Sent along with every 429
+        retry_after: Option<&'static str>,
+    }
+
+    async fn receive(
+        State(stand_in): State<Arc<StandIn>>,
+        headers: HeaderMap,
+        body: String,
+    ) -> (axum::http::StatusCode, HeaderMap) {
+        stand_in.requests.lock().unwrap().push((headers, body));
+
+        let mut statuses = stand_in.statuses.lock().unwrap();
+        let status = if statuses.is_empty() {
+            200
+        } else {
+            statuses.remove(0)
+        };
+
+        let mut headers = HeaderMap::new();
+        if let (429, Some(after)) = (status, stand_in.retry_after) {
+            headers.insert("retry-after", after.parse().unwrap());
+        }
+
+        (axum::http::StatusCode::from_u16(status).unwrap(), headers)
+    }
+
+    async fn stand_in(statuses: Vec<u16>) -> (Arc<StandIn>, Url) {
+        stand_in_with(StandIn {
+            statuses: Mutex::new(statuses),
+            ..Default::default()
+        })
+        .await
+    }
+
+    async fn stand_in_with(stand_in: StandIn) -> (Arc<StandIn>, Url) {
+        let stand_in = Arc::new(stand_in);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/hook", listener.local_addr().unwrap())).unwrap();
+
+        let app = Router::new()
+            .route("/hook", post(receive))
+            .with_state(stand_in.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        (stand_in, url)
+    }
+
+    fn hook(sink: Sink, url: Url) -> Webhook {
+        Webhook {
+            id: 1,
+            server: "meow".into(),
+            sink,
+            url,
+            events: parse_events(None).unwrap(),
+        }
+    }
+
+    const FAST: Backoff = Backoff {
+        attempts: 3,
+        initial: Duration::from_millis(10),
+    };
+
+    #[tokio::test]
+    async fn retries_until_delivered() {
+        let (stand_in, url) = stand_in(vec![500, 429]).await;
+
+        deliver(
+            &reqwest::Client::new(),
+            &hook(Sink::Discord, url),
+            &Event::Recovered,
+            &FAST,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(stand_in.requests.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up() {
+        let (stand_in, url) = stand_in(vec![503; 5]).await;
+
+        let res = deliver(
+            &reqwest::Client::new(),
+            &hook(Sink::Json, url),
+            &Event::Recovered,
+            &FAST,
+        )
+        .await;
+
+        assert!(res.is_err());
+        assert_eq!(stand_in.requests.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn waits_for_retry_after() {
+        let (stand_in, url) = stand_in_with(StandIn {
+            statuses: Mutex::new(vec![429]),
+            retry_after: Some("0.5"),
+            ..Default::default()
+        })
+        .await;
+
+        let start = std::time::Instant::now();
+        deliver(
+            &reqwest::Client::new(),
+            &hook(Sink::Discord, url),
+            &Event::Recovered,
+            &FAST,
+        )
+        .await
+        .unwrap();
+
+        assert!(start.elapsed() >= Duration::from_millis(500));
+        assert_eq!(stand_in.requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn long_rate_limits_give_up() {
+        let (stand_in, url) = stand_in_with(StandIn {
+            statuses: Mutex::new(vec![429]),
+            retry_after: Some("3600"),
+            ..Default::default()
+        })
+        .await;
+
+        let res = deliver(
+            &reqwest::Client::new(),
+            &hook(Sink::Discord, url),
+            &Event::Recovered,
+            &FAST,
+        )
+        .await;
+
+        assert!(res.is_err());
+        assert_eq!(stand_in.requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn errors_hide_the_url() {
+        // nothing listens on the discard port
+        let url = Url::parse("http://127.0.0.1:9/api/webhooks/1/hunter2").unwrap();
+
+        let error = deliver(
+            &reqwest::Client::new(),
+            &hook(Sink::Discord, url),
+            &Event::Recovered,
+            &FAST,
+        )
+        .await
+        .unwrap_err();
+
+        assert!(!error.to_string().contains("hunter2"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn client_errors_arent_retried() {
+        let (stand_in, url) = stand_in(vec![404]).await;
+
+        let res = deliver(
+            &reqwest::Client::new(),
+            &hook(Sink::Ntfy, url),
+            &Event::Recovered,
+            &FAST,
+        )
+        .await;
+
+        assert!(res.is_err());
+        assert_eq!(stand_in.requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn payloads() {
+        let event = Event::MapChange {
+            from: "dz_blacksite".into(),
+            to: "dz_sirocco".into(),
+        };
+        let client = reqwest::Client::new();
+
+        for sink in [Sink::Discord, Sink::Json, Sink::Ntfy] {
+            let (stand_in, url) = stand_in(vec![]).await;
+            deliver(&client, &hook(sink, url), &event, &FAST)
+                .await
+                .unwrap();
+
+            let (headers, body) = stand_in.requests.lock().unwrap().remove(0);
+
+            match sink {
+                Sink::Discord => {
+                    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+                    assert_eq!(
+                        body["content"],
+                        "meow changed map from dz_blacksite to dz_sirocco"
+                    );
+                }
+                Sink::Json => {
+                    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+                    assert_eq!(body["server"], "meow");
+                    assert_eq!(body["event"], "map_change");
+                    assert_eq!(body["from"], "dz_blacksite");
+                    assert_eq!(body["to"], "dz_sirocco");
+                }
+                Sink::Ntfy => {
+                    assert_eq!(headers["title"], "meow: Map changed");
+                    assert_eq!(headers["tags"], "map_change");
+                    assert_eq!(body, "meow changed map from dz_blacksite to dz_sirocco");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn events_are_validated() {
+        assert_eq!(
+            parse_events(Some("down, full".into())).unwrap(),
+            vec!["down", "full"]
+        );
+        assert!(parse_events(Some("explosion".into())).is_err());
+        assert!(parse_events(Some(" ".into())).is_err());
+    }
+}
@@ -1,4 +1,5 @@
 use crate::server_info::Info;
+use axum::extract::Query;
 use axum::extract::State;
 use axum::Json;
 use axum::{
@@ -11,38 +12,25 @@ use axum::{
 };
 use serde::Deserialize;
 use serde_json::from_slice;
-use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::net::TcpListener;
 use urlencoding::decode;
 
 use poise::serenity_prelude as serenity;
-use poise::serenity_prelude::prelude::TypeMapKey;
 
-use crate::server_info::get_server_info;
-use crate::socket::ServerSocket;
+use crate::db::DbConnection;
+use crate::gsi::model::GameState;
+use crate::gsi::{receive, Received};
+use crate::leaderboard::db::{read_leaderboard, LeaderboardEntry};
+use crate::leaderboard::Period;
+use crate::time::now;
+use crate::poller::{get_server_info, Pollers};
 use crate::Error;
 
 mod style;
 use style::{ClassName, STYLE_SHEET};
 
-#[derive(Deserialize, Debug)]
-struct GameStateIntegrationData {
-    token: String,
-}
-
-impl TypeMapKey for GameStateIntegrationData {
-    // name, data
-    type Value = HashMap<String, GameStateIntegrationData>;
-}
-
-struct GameStateIntegrationTokens;
-impl TypeMapKey for GameStateIntegrationTokens {
-    // token, name
-    type Value = HashMap<String, String>;
-}
-
 async fn gamestate_handler(
     State(ctx): State<Arc<serenity::Context>>,
     request: Request,
@@ -51,32 +39,21 @@ async fn gamestate_handler(
         .await
         .unwrap_or_default();
 
-    match from_slice::<GameStateIntegrationData>(&body_bytes) {
-        Ok(payload) => {
-            println!("Recieved JSON: {:?}", payload);
-
-            tokio::spawn(async move {
-                let mut data = ctx.data.write().await;
-
-                let tokens = data.get::<GameStateIntegrationTokens>().unwrap();
-
-                if let Some(name) = tokens.get(&payload.token) {
-                    let name = name.clone();
-
-                    match data.get_mut::<GameStateIntegrationData>() {
-                        Some(gsi) => {
-                            _ = gsi.insert(name, payload);
-                        }
-                        None => eprintln!("Unable to get gamestate_integration data."),
-                    };
-                }
-            });
-
-            (StatusCode::OK, "OK")
-        }
+    let payload = match from_slice::<GameState>(&body_bytes) {
+        Ok(v) => v,
         Err(e) => {
             println!("Invalid JSON: {:?}", e);
-            (StatusCode::BAD_REQUEST, "Invalid JSON")
+            return (StatusCode::BAD_REQUEST, "Invalid JSON");
+        }
+    };
+
+    match receive(&ctx, payload).await {
+        Ok(Received::Stored) => (StatusCode::OK, "OK"),
+        Ok(Received::MissingToken) => (StatusCode::UNAUTHORIZED, "Missing auth token"),
+        Ok(Received::UnknownToken) => (StatusCode::FORBIDDEN, "Unknown auth token"),
+        Err(e) => {
+            eprintln!("Unable to store gamestate: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Unable to store gamestate")
         }
     }
 }
@@ -177,14 +154,14 @@ async fn server_data(
     Path(path): Path<String>,
 ) -> Result<Json<Info>, impl IntoResponse> {
     let data = ctx.data.read().await;
-    let socks = match data.get::<ServerSocket>() {
+    let pollers = match data.get::<Pollers>() {
         Some(v) => v,
         None => {
-            return Err(Json("DataError: Unable to get sockets".to_string()));
+            return Err(Json("DataError: Unable to get pollers".to_string()));
         }
     };
 
-    let info = match get_server_info(socks, &path).await {
+    let info = match get_server_info(pollers, &path).await {
         Ok(v) => v,
         Err(e) => {
             return Err(Json(format!("Error: {e:?}")));
@@ -194,6 +171,37 @@ async fn server_data(
     Ok(Json(info))
 }
 
+#[derive(Deserialize)]
+struct LeaderboardQuery {
+    #[serde(default)]
+    period: Period,
+}
+
+/// Leaderboard of a server as a json blob, `?period=day|week|month|all`, defaults to week
+async fn leaderboard_data(
+    State(ctx): State<Arc<serenity::Context>>,
+    Path(path): Path<String>,
+    Query(query): Query<LeaderboardQuery>,
+) -> Result<Json<Vec<LeaderboardEntry>>, impl IntoResponse> {
+    let now = match now() {
+        Ok(v) => v,
+        Err(e) => return Err(Json(format!("Error: {e:?}"))),
+    };
+
+    let mut data = ctx.data.write().await;
+    let conn = match data.get_mut::<DbConnection>() {
+        Some(v) => v,
+        None => {
+            return Err(Json("DataError: Unable to get database connection".to_string()));
+        }
+    };
+
+    match read_leaderboard(conn, &path, query.period.since(now), now, 100).await {
+        Ok(v) => Ok(Json(v)),
+        Err(e) => Err(Json(format!("Error: {e:?}"))),
+    }
+}
+
 async fn alive_check(
     State(ctx): State<Arc<serenity::Context>>
 ) -> StatusCode {
@@ -211,6 +219,7 @@ pub async fn server(ctx: Arc<serenity::Context>) -> Result<(), Error> {
         .route("/", get(main_page))
         .route("/health", get(alive_check))
         .route("/data/{*path}", get(server_data))
+        .route("/leaderboard/{*path}", get(leaderboard_data))
         .route("/{*path}", get(steam_connect))
         .nest_service("/static", ServeDir::new("static"))
         .with_state(ctx);